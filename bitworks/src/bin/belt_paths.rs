@@ -1,6 +1,10 @@
 use bevy::prelude::*;
 use bitworks::*;
 
+/// the first two items of `items.ron`
+const RED: ItemId = ItemId(0);
+const GREEN: ItemId = ItemId(1);
+
 fn main() {
     belts_example_app().run();
 }
@@ -10,6 +14,7 @@ pub fn belts_example_app() -> AppBuilder {
     app.add_plugins(DefaultPlugins)
         .add_plugin(DebugLinesPlugin)
         .add_plugin(BeltDebugPlugin)
+        .add_plugin(SimulationPlugin)
         .add_plugin(BeltPlugin)
         .add_startup_system(setup.system());
     app
@@ -52,7 +57,7 @@ fn setup(mut cmds: Commands) {
 
         (Merger {
             cooldown: 0.0,
-            next_time: 0.0,
+            next_time: 0,
             items_per_step: 1,
             input_cursor: 0,
             output_cursor: 0,
@@ -76,9 +81,15 @@ fn belt1() -> impl Bundle {
                 BeltSegment::straight(-30, 30, 0, 30),
                 BeltSegment::straight(0, 30, 30, 50),
             ],
-            items: vec![BeltItem::red(0.0), BeltItem::green(30.0)],
+            lanes: Default::default(),
             output: None,
-        },
+            output_lane: None,
+            tier: BeltTier::Basic,
+        }
+        .with_items(vec![
+            BeltItem::new(0.0, RED).on_lane(Lane::Left),
+            BeltItem::new(30.0, GREEN),
+        ]),
         ItemInput::new(2),
     )
 }
@@ -90,9 +101,15 @@ fn belt2(output: Entity) -> impl Bundle {
                 BeltSegment::straight(-30, 10, 0, 0),
                 BeltSegment::straight(0, 0, 30, -20),
             ],
-            items: vec![BeltItem::red(0.0), BeltItem::green(30.0)],
+            lanes: Default::default(),
             output: Some(output),
-        },
+            output_lane: None,
+            tier: BeltTier::Basic,
+        }
+        .with_items(vec![
+            BeltItem::new(0.0, RED).on_lane(Lane::Left),
+            BeltItem::new(30.0, GREEN),
+        ]),
         ItemInput::new(2),
     )
 }
@@ -104,9 +121,15 @@ fn belt3(output: Entity) -> impl Bundle {
                 BeltSegment::straight(-30, -30, 0, -30),
                 BeltSegment::straight(0, -30, 30, -30),
             ],
-            items: vec![BeltItem::red(0.0), BeltItem::green(30.0)],
+            lanes: Default::default(),
             output: Some(output),
-        },
+            output_lane: None,
+            tier: BeltTier::Basic,
+        }
+        .with_items(vec![
+            BeltItem::new(0.0, RED).on_lane(Lane::Left),
+            BeltItem::new(30.0, GREEN),
+        ]),
         ItemInput::new(2),
     )
 }
@@ -115,8 +138,10 @@ fn belt(x: i32, y: i32, output: Entity) -> impl Bundle {
     (
         Belt {
             segments: vec![BeltSegment::straight(x, y, x + 30, y)],
-            items: vec![],
+            lanes: Default::default(),
             output: Some(output),
+            output_lane: None,
+            tier: BeltTier::Basic,
         },
         ItemInput::new(2),
     )
@@ -131,7 +156,7 @@ fn item_generator(belt: Entity, pos: Vec2, cooldown: f32) -> impl Bundle {
         pos,
         RandomItemGenerator {
             cooldown,
-            next_time: 0.0,
+            next_time: 0,
             output: Some(belt),
         },
     )
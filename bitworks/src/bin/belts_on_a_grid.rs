@@ -24,7 +24,8 @@ pub fn belts_example_app() -> AppBuilder {
         app.add_plugin(LogDiagnosticsPlugin::default());
     }
 
-    app.add_state(AppState::GamePaused)
+    app.insert_resource(SimRng::from_args_or_config(Some(&config)))
+        .add_state(AppState::GamePaused)
         .add_plugins(DefaultPlugins)
         .add_plugin(DiagnosticsPlugin)
         .add_plugin(FrameTimeDiagnosticsPlugin)
@@ -40,7 +41,18 @@ pub fn belts_example_app() -> AppBuilder {
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
         .add_plugin(GameStatePlugin)
         .add_plugin(BeltInputOutputHookupPlugin)
+        .add_plugin(SimulationPlugin)
         .add_plugin(BeltPlugin)
+        .add_plugin(AssemblerPlugin)
+        .add_plugin(ChestPlugin)
+        .add_plugin(InserterPlugin)
+        .add_plugin(DepositsPlugin)
+        .add_plugin(MiningDrillPlugin)
+        .add_plugin(PowerPlugin)
+        .add_plugin(FluidPlugin)
+        .add_plugin(SavePlugin)
+        .add_plugin(BeltToolPlugin)
+        .add_plugin(MapEditPlugin)
         .add_plugin(BeltGraphicsPlugin)
         .add_plugin(PickingPlugin)
         .add_plugin(InteractablePickingPlugin)
@@ -61,9 +73,108 @@ struct SetupPlugin;
 impl Plugin for SetupPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.add_startup_system(setup.system())
+            .add_startup_system(spawn_ground.system())
+            .add_startup_system(spawn_deposits.system().after("deposits"))
             .add_startup_system(setup_rapier.system().label("rapier"))
             .add_startup_system(spawn_yellow_obstacle.system().after("rapier"))
-            .add_startup_system(spawn_player.system().after("rapier"));
+            .add_startup_system(spawn_player.system().after("rapier"))
+            .add_system(save_load_keys_system.exclusive_system())
+            .add_system(demolish_system.system());
+    }
+}
+
+const SAVE_PATH: &str = "world.ron";
+
+/// F5 saves the world, F9 loads it again
+fn save_load_keys_system(world: &mut World) {
+    let keys = world.get_resource::<Input<KeyCode>>().expect("InputPlugin");
+    let (save, load) = (
+        keys.just_pressed(KeyCode::F5),
+        keys.just_pressed(KeyCode::F9),
+    );
+
+    if save {
+        save_world(SAVE_PATH)(world);
+    }
+    if load {
+        load_world(SAVE_PATH)(world);
+        // the edits were made to another world
+        if let Some(mut undo) = world.get_resource_mut::<UndoStack<MapEdit>>() {
+            undo.clear();
+        }
+    }
+}
+
+/// Delete removes the building under the mouse, it can be undone
+fn demolish_system(
+    keys: Res<Input<KeyCode>>,
+    cameras: Query<&PickingCamera>,
+    map: Res<MapCache>,
+    buildings: Query<(), With<Placed>>,
+    mut undo: ResMut<UndoStack<MapEdit>>,
+) {
+    if !keys.just_pressed(KeyCode::Delete) {
+        return;
+    }
+    let cell = match cursor_cell(&cameras) {
+        Some(cell) => cell,
+        None => return,
+    };
+
+    let building = [MapLayer::Buildings, MapLayer::Belts, MapLayer::Pipes]
+        .iter()
+        .filter_map(|layer| map.at(*layer, &cell))
+        .find(|entity| buildings.get(*entity).is_ok());
+    if let Some(building) = building {
+        undo.push(MapEdit::Remove(building, None));
+    }
+}
+
+/// behind everything else, the belt tool picks the cell under the mouse on it
+fn spawn_ground(
+    mut cmds: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    cmds.spawn()
+        .insert(Name::new("Ground"))
+        .insert_bundle(PbrBundle {
+            mesh: meshes.add(
+                shape::Plane {
+                    size: 200.0 * TILE_SIZE,
+                }
+                .into(),
+            ),
+            material: materials.add(StandardMaterial::unlit_color(Color::rgb(0.1, 0.1, 0.1))),
+            transform: Transform {
+                translation: vec3(0.0, 0.0, -2.0),
+                rotation: Quat::from_rotation_x(std::f32::consts::FRAC_PI_2),
+                scale: Vec3::ONE,
+            },
+            ..Default::default()
+        })
+        .insert_bundle(PickableBundle::default());
+}
+
+fn spawn_deposits(mut cmds: Commands, deposits: Res<Deposits>, registry: Res<ItemRegistry>) {
+    for (pos, deposit) in deposits.iter() {
+        let mut color = registry.color(deposit.item);
+        color.set_a(0.3);
+        let corner = vec3(-TILE_HALFSIZE, TILE_HALFSIZE, -1.0);
+
+        cmds.spawn()
+            .insert(Name::new("Deposit"))
+            .insert(*pos)
+            .insert(DepositTile)
+            .insert(Transform::default())
+            .insert(GlobalTransform::default())
+            .with_children(|child| {
+                child.spawn_bundle(
+                    lyon()
+                        .rectangle(TILE_SIZE, TILE_SIZE)
+                        .outlined_pos3(color, color, 1.0, corner),
+                );
+            });
     }
 }
 
@@ -108,19 +219,28 @@ fn setup(mut cmds: Commands) {
     use CompassDir::*;
     for simple in vec![
         Simple::ItemGenerator(map_pos(-3, 2), E),
-        Simple::Belt(map_pos(-2, 2), W, E),
-        Simple::Belt(map_pos(-1, 2), W, E),
+        Simple::Belt(map_pos(-2, 2), W, E, BeltTier::Basic),
+        Simple::Belt(map_pos(-1, 2), W, E, BeltTier::Basic),
+        // side loading onto the left lane of the belt above
+        Simple::ItemGenerator(map_pos(-1, 4), S),
+        Simple::Belt(map_pos(-1, 3), N, S, BeltTier::Basic),
         //
         Simple::ItemGenerator(map_pos(-3, 0), E),
-        Simple::Belt(map_pos(-2, 0), W, N),
-        Simple::Belt(map_pos(-2, 1), S, E),
-        Simple::Belt(map_pos(-1, 1), W, E),
+        Simple::Belt(map_pos(-2, 0), W, N, BeltTier::Basic),
+        Simple::Belt(map_pos(-2, 1), S, E, BeltTier::Basic),
+        Simple::Belt(map_pos(-1, 1), W, E, BeltTier::Basic),
         //
         Simple::Merger2x2(map_pos(0, 2), E),
         //
-        Simple::Belt(map_pos(1, 2), W, E),
-        Simple::Belt(map_pos(1, 1), W, E),
+        Simple::Belt(map_pos(1, 2), W, E, BeltTier::Fast),
+        Simple::Belt(map_pos(1, 1), W, E, BeltTier::Basic),
         Simple::NullSink(map_pos(2, 2), W),
+        //
+        Simple::ItemGenerator(map_pos(-2, -3), E),
+        Simple::Belt(map_pos(-1, -3), W, E, BeltTier::Basic),
+        Simple::Assembler(map_pos(0, -3), E, "brown".into()),
+        Simple::Belt(map_pos(2, -3), W, E, BeltTier::Basic),
+        Simple::NullSink(map_pos(3, -3), W),
     ] {
         cmds.spawn_bundle((simple,));
     }
@@ -0,0 +1,245 @@
+//! Runs a belt layout without window and rendering and prints what arrived where.
+//!
+//! `cargo run --bin bitworks-sim -- bitworks/layouts/merger.ron 3600 --seed 42`
+//!
+//! The layout file is a RON list of `Simple` entries or a `WorldSave`,
+//! a save runs on for `ticks` more. `--save <path>` writes the world at the end.
+
+use bevy::{prelude::*, utils::HashMap};
+
+use bitworks::*;
+
+const DEFAULT_TICKS: u64 = 60 * TICKS_PER_SECOND as u64;
+const USAGE: &str = "usage: bitworks-sim <layout.ron> [ticks] [--seed <u64>] [--save <path>]";
+
+/// The command line, anything it does not expect is an error.
+struct Args {
+    layout: String,
+    ticks: u64,
+    seed: Option<u64>,
+    save: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut seed = None;
+        let mut save = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    let value = args.next().ok_or("--seed needs a value")?;
+                    let value = value
+                        .parse()
+                        .map_err(|_| format!("invalid seed {:?}", value))?;
+                    seed = Some(value);
+                }
+                "--save" => save = Some(args.next().ok_or("--save needs a path")?),
+                flag if flag.starts_with("--") => return Err(format!("unknown flag {}", flag)),
+                _ => positional.push(arg.clone()),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let layout = positional.next().ok_or("no layout given")?;
+        let ticks = match positional.next() {
+            Some(ticks) => ticks
+                .parse()
+                .map_err(|_| format!("invalid tick count {:?}", ticks))?,
+            None => DEFAULT_TICKS,
+        };
+        if let Some(extra) = positional.next() {
+            return Err(format!("unexpected argument {:?}", extra));
+        }
+
+        Ok(Self {
+            layout,
+            ticks,
+            seed,
+            save,
+        })
+    }
+}
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let layout_path = args.layout.as_str();
+    let ticks = args.ticks;
+
+    let mut app = headless_app();
+    if let Some(seed) = args.seed {
+        app.insert_resource(SimRng::new(seed));
+    }
+    let ticks = match load_layout(layout_path) {
+        Ok(layout) => {
+            for simple in layout {
+                app.world_mut().spawn().insert(simple);
+            }
+            ticks
+        }
+        Err(layout_err) => match WorldSave::read(layout_path) {
+            // loaded after the deposits are generated, so the saved ones replace them
+            Ok(save) => {
+                app.add_startup_system(load_world(layout_path).exclusive_system().at_end());
+                save.tick + ticks
+            }
+            Err(_) => {
+                eprintln!("failed to load layout {}: {}", layout_path, layout_err);
+                std::process::exit(1);
+            }
+        },
+    };
+
+    let mut app = app.app;
+    while sim_tick(&app.world) < ticks {
+        app.update();
+    }
+
+    if let Some(save_path) = args.save {
+        if let Err(err) = WorldSave::capture(&mut app.world).write(&save_path) {
+            eprintln!("failed to save world to {}: {}", save_path, err);
+            std::process::exit(1);
+        }
+    }
+
+    print_report(&mut app.world);
+}
+
+pub fn headless_app() -> AppBuilder {
+    let mut app = App::build();
+    app.insert_resource(SimulationTime::stepped(1))
+        .add_plugins(MinimalPlugins)
+        .add_plugin(SimulationPlugin)
+        .add_plugin(MapPlugin)
+        .add_plugin(SimpleSpawnerPlugin)
+        .add_plugin(BeltInputOutputHookupPlugin)
+        .add_plugin(BeltPlugin)
+        .add_plugin(AssemblerPlugin)
+        .add_plugin(ChestPlugin)
+        .add_plugin(InserterPlugin)
+        .add_plugin(DepositsPlugin)
+        .add_plugin(MiningDrillPlugin)
+        .add_plugin(PowerPlugin)
+        .add_plugin(FluidPlugin)
+        .add_plugin(SavePlugin);
+    app
+}
+
+fn load_layout(path: &str) -> Result<Vec<Simple>, ron::Error> {
+    let file = std::fs::File::open(path)?;
+    ron::de::from_reader(file)
+}
+
+fn sim_tick(world: &World) -> u64 {
+    world
+        .get_resource::<SimulationTime>()
+        .expect("SimulationPlugin")
+        .tick()
+}
+
+fn print_report(world: &mut World) {
+    let tick = sim_tick(world);
+    let seconds = tick as f64 / TICKS_PER_SECOND as f64;
+    let seed = world.get_resource::<SimRng>().expect("SimRng").seed();
+    println!("ticks {} ({:.1}s) seed {}", tick, seconds, seed);
+
+    let mut sinks: Vec<(MapPos, u64)> = world
+        .query::<(&MapPos, &NullSink)>()
+        .iter(world)
+        .map(|(pos, sink)| (*pos, sink.consumed()))
+        .collect();
+    sinks.sort_by_key(|(pos, _)| (pos.x, pos.y));
+
+    println!("sinks");
+    for (pos, consumed) in sinks {
+        println!(
+            "  ({:>3}, {:>3})  {:>6} items  {:>6.2} items/s",
+            pos.x,
+            pos.y,
+            consumed,
+            consumed as f64 / seconds.max(f64::EPSILON)
+        );
+    }
+
+    let mut chests: Vec<(MapPos, Inventory)> = world
+        .query_filtered::<(&MapPos, &Inventory), With<Chest>>()
+        .iter(world)
+        .map(|(pos, inventory)| (*pos, inventory.clone()))
+        .collect();
+    chests.sort_by_key(|(pos, _)| (pos.x, pos.y));
+
+    let registry = world.get_resource::<ItemRegistry>().expect("ItemRegistry");
+    println!("chests");
+    for (pos, inventory) in chests {
+        let contents: Vec<String> = registry
+            .ids()
+            .map(|item| (item, inventory.count(item)))
+            .filter(|(_, count)| *count > 0)
+            .map(|(item, count)| format!("{} {}", registry.get(item).id, count))
+            .collect();
+        println!("  ({:>3}, {:>3})  {}", pos.x, pos.y, contents.join(", "));
+    }
+
+    let mut tanks: Vec<(MapPos, FluidBox)> = world
+        .query_filtered::<(&MapPos, &FluidBox), With<Tank>>()
+        .iter(world)
+        .map(|(pos, fluid_box)| (*pos, fluid_box.clone()))
+        .collect();
+    tanks.sort_by_key(|(pos, _)| (pos.x, pos.y));
+
+    let registry = world.get_resource::<ItemRegistry>().expect("ItemRegistry");
+    println!("tanks");
+    for (pos, fluid_box) in tanks {
+        let fluid = fluid_box
+            .fluid
+            .map_or("empty", |it| registry.get(it).id.as_str());
+        println!(
+            "  ({:>3}, {:>3})  {} {:.1}/{:.0}",
+            pos.x, pos.y, fluid, fluid_box.amount, fluid_box.capacity
+        );
+    }
+
+    let networks = world
+        .get_resource::<PowerNetworks>()
+        .expect("PowerNetworks");
+    println!("power");
+    for network in networks.networks.iter() {
+        println!(
+            "  {:>3} poles  {:>8.1} W produced  {:>8.1} W needed  {:>5.1}%",
+            network.poles,
+            network.production,
+            network.demand,
+            100.0 * network.satisfaction
+        );
+    }
+
+    // fused belts carry no items, the first belt of their line has them
+    let mut counts: HashMap<Entity, usize> = HashMap::default();
+    for (entity, belt, line) in world
+        .query_filtered::<(Entity, &Belt, Option<&BeltLine>), Without<FusedInto>>()
+        .iter(world)
+    {
+        for item in belt.items() {
+            let owner = line.map_or(entity, |line| line.belt_at(entity, item.pos));
+            *counts.entry(owner).or_default() += 1;
+        }
+    }
+
+    let mut belts: Vec<(MapPos, usize)> = world
+        .query_filtered::<(Entity, &MapPos), With<Belt>>()
+        .iter(world)
+        .map(|(entity, pos)| (*pos, counts.get(&entity).copied().unwrap_or(0)))
+        .collect();
+    belts.sort_by_key(|(pos, _)| (pos.x, pos.y));
+
+    println!("belts");
+    for (pos, count) in belts {
+        println!("  ({:>3}, {:>3})  {:>3} items", pos.x, pos.y, count);
+    }
+}
@@ -1,10 +1,6 @@
 use std::f32::consts::{FRAC_PI_2, FRAC_PI_3, FRAC_PI_4, PI};
 
-use bevy::{
-    ecs::component::Component,
-    input::{mouse::MouseButtonInput, ElementState},
-    utils::HashMap,
-};
+use bevy::{ecs::component::Component, utils::HashMap};
 use bitworks::*;
 
 use bevy_egui::{egui, EguiContext, EguiPlugin};
@@ -24,6 +20,7 @@ fn main() {
         //.add_plugin(DebugCursorPickingPlugin)
         //.add_plugin(DefaultRaycastingPlugin::<MyRaycastSet>::default())
         .add_plugin(RaycastPlugin)
+        .add_plugin(UndoPlugin::<FieldEdit>::default())
         .add_plugin(Setup);
     app.run();
 }
@@ -37,7 +34,13 @@ impl Plugin for Setup {
             .insert_resource(Models::default())
             .insert_resource(Field::default())
             .add_system(tool_ui.system())
-            .add_system(build_on_click.system())
+            .add_system(build_on_click.system().label("build_on_click"))
+            .add_system(
+                field_edit_system
+                    .system()
+                    .after("build_on_click")
+                    .after("undo_keys"),
+            )
             .add_system_to_stage(CoreStage::PreUpdate, update_raycast_with_cursor.system())
             .add_system(update_plane_selector_with_raycast_source.system())
             .add_system(update_build_ghost.system())
@@ -150,72 +153,125 @@ fn update_random_walker(time: Res<Time>, mut walkers: Query<(&mut Transform, &mu
 
 //
 
+/// every cell the mouse is dragged over is one edit, the whole drag is one undo step
 fn build_on_click(
-    mut events: EventReader<MouseButtonInput>,
+    buttons: Res<Input<MouseButton>>,
     plane_selector_query: Query<&Transform, With<PlaneSelector>>,
     tool: Res<Tool>,
+    field: Res<Field>,
+    mut undo: ResMut<UndoStack<FieldEdit>>,
+    mut last_cell: Local<Option<IVec3>>,
+) {
+    if buttons.just_pressed(MouseButton::Left) {
+        undo.begin_group();
+        *last_cell = None;
+    }
+
+    if buttons.pressed(MouseButton::Left) {
+        if let Ok(transform) = plane_selector_query.single() {
+            let at = transform.translation;
+            if *last_cell != Some(at.as_i32()) {
+                *last_cell = Some(at.as_i32());
+
+                let edit = match (*tool, field.building_at(at)) {
+                    (Tool::Clear, Some(building)) => Some(FieldEdit::Clear(at, building)),
+                    (Tool::Clear, None) | (_, Some(_)) => None,
+                    (tool, None) => Some(FieldEdit::Build(at, tool)),
+                };
+                if let Some(edit) = edit {
+                    undo.push(edit);
+                }
+            }
+        }
+    }
+
+    if buttons.just_released(MouseButton::Left) {
+        undo.end_group();
+    }
+}
+
+/// A building built or cleared at a cell, with the tool it was built with.
+#[derive(Debug, Clone, Copy)]
+enum FieldEdit {
+    Build(Vec3, Tool),
+    Clear(Vec3, Tool),
+}
+
+impl Edit for FieldEdit {
+    fn inverse(&self) -> Self {
+        match *self {
+            FieldEdit::Build(at, tool) => FieldEdit::Clear(at, tool),
+            FieldEdit::Clear(at, tool) => FieldEdit::Build(at, tool),
+        }
+    }
+}
+
+fn field_edit_system(
     mut cmds: Commands,
     models: Res<Models>,
     mut field: ResMut<Field>,
+    mut undo: ResMut<UndoStack<FieldEdit>>,
 ) {
     let cmds = &mut cmds;
     let field = &mut field;
 
-    for event in events.iter() {
-        if let (MouseButton::Left, ElementState::Pressed) = (event.button, event.state) {
-            if let Ok(transform) = plane_selector_query.single() {
-                let tool = *tool;
-                let transform = Transform::from_translation(transform.translation);
-
-                match tool {
-                    Tool::Clear => try_clear(cmds, transform, field),
-                    Tool::Spring => try_build(cmds, transform, &models.building_spring, field),
-                    Tool::Glassblower => {
-                        try_build(cmds, transform, &models.building_glassblower, field)
-                    }
-                    Tool::Tap => try_build(cmds, transform, &models.building_tap, field),
-                    Tool::Trash => try_build(cmds, transform, &models.building_trash, field),
-                }
-            }
-        }
-    }
+    undo.apply_pending(|edit| {
+        let applied = match edit {
+            FieldEdit::Build(at, tool) => models.building(tool).map_or(false, |model| {
+                try_build(cmds, Transform::from_translation(at), tool, model, field)
+            }),
+            FieldEdit::Clear(at, _) => try_clear(cmds, Transform::from_translation(at), field),
+        };
+        applied.then(|| edit)
+    });
+}
 
-    fn try_clear(cmds: &mut Commands, transform: Transform, field: &mut Field) {
-        if let Some(entity) = field.clear_building(transform.translation) {
+fn try_clear(cmds: &mut Commands, transform: Transform, field: &mut Field) -> bool {
+    match field.clear_building(transform.translation) {
+        Some((entity, _)) => {
             cmds.entity(entity).despawn_recursive();
+            true
         }
+        None => false,
     }
+}
 
-    fn try_build(cmds: &mut Commands, transform: Transform, model: &Model, field: &mut Field) {
-        if field.has_building(transform.translation) {
-            return;
-        }
+fn try_build(
+    cmds: &mut Commands,
+    transform: Transform,
+    tool: Tool,
+    model: &Model,
+    field: &mut Field,
+) -> bool {
+    if field.has_building(transform.translation) {
+        return false;
+    }
 
-        let entity = cmds
-            .spawn_bundle((
-                transform,
+    let entity = cmds
+        .spawn_bundle((
+            transform,
+            GlobalTransform::identity(),
+            BuildAnimation::default(),
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(model.bundle())
+                .insert_bundle(PickableBundle::default());
+            parent.spawn_bundle((
                 GlobalTransform::identity(),
-                BuildAnimation::default(),
-            ))
-            .with_children(|parent| {
-                parent
-                    .spawn_bundle(model.bundle())
-                    .insert_bundle(PickableBundle::default());
-                parent.spawn_bundle((
-                    GlobalTransform::identity(),
-                    Transform::from_translation(vec3(1.0, 0.0, 0.0)),
-                    Producer {
-                        production: vec![ProducerEntry {
-                            time: 1.0,
-                            product: Product::RandomWalker,
-                        }],
-                    },
-                ));
-            })
-            .id();
-
-        field.set_building(transform.translation, entity);
-    }
+                Transform::from_translation(vec3(1.0, 0.0, 0.0)),
+                Producer {
+                    production: vec![ProducerEntry {
+                        time: 1.0,
+                        product: Product::RandomWalker,
+                    }],
+                },
+            ));
+        })
+        .id();
+
+    field.set_building(transform.translation, entity, tool);
+    true
 }
 
 //
@@ -226,7 +282,7 @@ struct Field {
 }
 
 impl Field {
-    fn clear_building(&mut self, at: Vec3) -> Option<Entity> {
+    fn clear_building(&mut self, at: Vec3) -> Option<(Entity, Tool)> {
         if let Some(cell) = self.cells.get_mut(&at.as_i32()) {
             cell.building.take()
         } else {
@@ -234,21 +290,26 @@ impl Field {
         }
     }
 
-    fn set_building(&mut self, at: Vec3, entity: Entity) {
-        self.cells.entry(at.as_i32()).or_default().building = Some(entity);
+    fn set_building(&mut self, at: Vec3, entity: Entity, tool: Tool) {
+        self.cells.entry(at.as_i32()).or_default().building = Some((entity, tool));
     }
 
     fn has_building(&self, at: Vec3) -> bool {
+        self.building_at(at).is_some()
+    }
+
+    /// the tool the building at `at` was built with
+    fn building_at(&self, at: Vec3) -> Option<Tool> {
         self.cells
             .get(&at.as_i32())
             .and_then(|cell| cell.building)
-            .is_some()
+            .map(|(_, tool)| tool)
     }
 }
 
 #[derive(Default)]
 struct FieldCell {
-    building: Option<Entity>,
+    building: Option<(Entity, Tool)>,
 }
 
 //
@@ -315,6 +376,18 @@ pub struct Models {
     random_walker: Model,
 }
 
+impl Models {
+    fn building(&self, tool: Tool) -> Option<&Model> {
+        match tool {
+            Tool::Clear => None,
+            Tool::Spring => Some(&self.building_spring),
+            Tool::Glassblower => Some(&self.building_glassblower),
+            Tool::Tap => Some(&self.building_tap),
+            Tool::Trash => Some(&self.building_trash),
+        }
+    }
+}
+
 fn setup_assets(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
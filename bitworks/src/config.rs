@@ -6,6 +6,9 @@ pub const TILE_HALFSIZE: f32 = 24.0;
 #[derive(Debug, Deserialize, Default)]
 pub struct Config {
     pub log_diagnostics: bool,
+    /// seed of `SimRng`, random if not given here or with `--seed`
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 impl Config {
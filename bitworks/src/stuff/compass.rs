@@ -1,6 +1,7 @@
 use bevy::math::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Copy)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Copy, Serialize, Deserialize)]
 pub enum CompassDir {
     N,
     E,
@@ -9,6 +10,8 @@ pub enum CompassDir {
 }
 
 impl CompassDir {
+    pub const ALL: [CompassDir; 4] = [CompassDir::N, CompassDir::E, CompassDir::S, CompassDir::W];
+
     pub fn opposite(&self) -> Self {
         use CompassDir::*;
         match self {
@@ -39,6 +42,27 @@ impl CompassDir {
         }
     }
 
+    /// turned like `N` would be turned to `by`, clockwise
+    pub fn rotated(&self, by: CompassDir) -> Self {
+        use CompassDir::*;
+        match by {
+            N => *self,
+            E => self.right(),
+            S => self.opposite(),
+            W => self.left(),
+        }
+    }
+
+    /// east and west swapped
+    pub fn mirrored(&self) -> Self {
+        use CompassDir::*;
+        match self {
+            E => W,
+            W => E,
+            N | S => *self,
+        }
+    }
+
     pub fn vec2(&self) -> Vec2 {
         use CompassDir::*;
         match self {
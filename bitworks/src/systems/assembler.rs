@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+
+use crate::{
+    powered_seconds, try_push_item_to_input, BeltItem, FluidBox, ItemId, ItemInput, ItemRegistry,
+    PowerConsumer, RecipeId, RecipeRegistry, SimulationStage, SimulationTime, TICK_SECONDS,
+};
+
+pub struct AssemblerPlugin;
+impl Plugin for AssemblerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if app.world().get_resource::<ItemRegistry>().is_none() {
+            app.insert_resource(ItemRegistry::load_default());
+        }
+        if app.world().get_resource::<RecipeRegistry>().is_none() {
+            let items = app.world().get_resource::<ItemRegistry>().expect("items");
+            let recipes = RecipeRegistry::load_default(items);
+            app.insert_resource(recipes);
+        }
+
+        app.add_system_to_stage(
+            SimulationStage,
+            assembler_system
+                .system()
+                .label("assembler")
+                .after("splitter"),
+        );
+    }
+}
+
+/// Crafts its recipe from ingredients taken out of its inputs
+/// and puts the products onto its output belts.
+pub struct Assembler {
+    pub recipe: RecipeId,
+    pub inputs: Vec<Entity>,
+    /// a `FluidBox` for fluid ingredients
+    pub fluid_input: Option<Entity>,
+    pub outputs: Vec<Option<Entity>>,
+    /// ingredients taken from the inputs, indexed like `Recipe::inputs`
+    pub buffer: Vec<u32>,
+    /// products waiting for space on an output belt
+    pub done: Vec<ItemId>,
+    /// simulation tick the current craft is finished, `None` while idle
+    pub craft_until: Option<u64>,
+    pub output_cursor: usize,
+}
+
+impl Assembler {
+    pub fn new(recipe: RecipeId, recipes: &RecipeRegistry, inputs: Vec<Entity>) -> Self {
+        Self {
+            recipe,
+            inputs,
+            fluid_input: None,
+            outputs: Vec::new(),
+            buffer: vec![0; recipes.get(recipe).inputs.len()],
+            done: Vec::new(),
+            craft_until: None,
+            output_cursor: 0,
+        }
+    }
+
+    /// takes `item` if the recipe needs it and there is room for it,
+    /// like from the inputs up to the ingredients of two crafts
+    pub fn insert_ingredient(&mut self, item: ItemId, recipes: &RecipeRegistry) -> bool {
+        let recipe = recipes.get(self.recipe);
+        match recipe.input_index(item) {
+            Some(index) if self.buffer[index] < 2 * recipe.inputs[index].1 => {
+                self.buffer[index] += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn assembler_system(
+    mut assemblers: Query<(&mut Assembler, Option<&PowerConsumer>)>,
+    mut inputs: Query<&mut ItemInput>,
+    mut fluid_boxes: Query<&mut FluidBox>,
+    recipes: Res<RecipeRegistry>,
+    registry: Res<ItemRegistry>,
+    sim_time: Res<SimulationTime>,
+) {
+    let tick = sim_time.tick();
+
+    for (mut assembler, power) in assemblers.iter_mut() {
+        let assembler = &mut *assembler;
+        let recipe = recipes.get(assembler.recipe);
+
+        // take ingredients for up to two crafts, items which are no ingredient
+        // are thrown away so they can not block the input
+        for input_e in assembler.inputs.iter() {
+            if let Ok(mut input) = inputs.get_mut(*input_e) {
+                while let Some(item) = input.oldest_item().map(|it| it.item) {
+                    match recipe.input_index(item) {
+                        Some(index) if assembler.buffer[index] < 2 * recipe.inputs[index].1 => {
+                            input.pop_oldest_item();
+                            assembler.buffer[index] += 1;
+                        }
+                        Some(_) => break,
+                        None => {
+                            input.pop_oldest_item();
+                        }
+                    }
+                }
+            }
+        }
+
+        // fluids are taken in whole units
+        if let Some(mut fluid_box) = assembler
+            .fluid_input
+            .and_then(|it| fluid_boxes.get_mut(it).ok())
+        {
+            for (index, (item, needed)) in recipe.inputs.iter().enumerate() {
+                while fluid_box.fluid == Some(*item)
+                    && assembler.buffer[index] < 2 * needed
+                    && fluid_box.take(1.0)
+                {
+                    assembler.buffer[index] += 1;
+                }
+            }
+        }
+
+        if let Some(until) = assembler.craft_until {
+            if until <= tick {
+                assembler.craft_until = None;
+                for (item, count) in recipe.outputs.iter() {
+                    assembler
+                        .done
+                        .extend(std::iter::repeat(*item).take(*count as usize));
+                }
+            }
+        }
+
+        let has_ingredients = recipe
+            .inputs
+            .iter()
+            .zip(assembler.buffer.iter())
+            .all(|((_, needed), buffered)| buffered >= needed);
+
+        // without power nothing new is crafted, less power crafts slower
+        let craft_time = powered_seconds(recipe.craft_ticks as f32 * TICK_SECONDS, power);
+
+        if let Some(craft_time) = craft_time {
+            if assembler.craft_until.is_none() && assembler.done.is_empty() && has_ingredients {
+                for ((_, needed), buffered) in recipe.inputs.iter().zip(assembler.buffer.iter_mut())
+                {
+                    *buffered -= needed;
+                }
+                assembler.craft_until = Some(sim_time.tick_after(craft_time));
+            }
+        }
+
+        // every output can take one product per tick
+        let len = assembler.outputs.len();
+        if assembler.output_cursor >= len {
+            assembler.output_cursor = 0;
+        }
+        for index in (assembler.output_cursor..len).chain(0..assembler.output_cursor) {
+            let item = match assembler.done.first() {
+                Some(item) => *item,
+                None => break,
+            };
+            let output = match assembler.outputs[index] {
+                Some(output) => output,
+                None => continue,
+            };
+
+            if let Ok(mut input) = inputs.get_mut(output) {
+                let mut belt_item = BeltItem::new(0.0, item);
+                if try_push_item_to_input(&mut belt_item, &mut input, &registry) {
+                    assembler.done.remove(0);
+                    assembler.output_cursor = (index + 1) % len;
+                }
+            }
+        }
+    }
+}
@@ -1,20 +1,59 @@
 use bevy::prelude::*;
 
 use crate::{
-    belt_advance_items_system, belt_input_system, merger_system, null_sink_system,
-    random_item_generator_system, AppState,
+    belt_advance_items_system, belt_input_system, belt_line_dirty_system, belt_line_system,
+    chunk_activity_system, merger_system, null_sink_system, random_item_generator_system,
+    splitter_system, AwakeChunks, BeltLines, ItemRegistry, SimulationStage,
 };
 
 pub struct BeltPlugin;
 impl Plugin for BeltPlugin {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system_to_stage(CoreStage::PreUpdate, belt_input_system.system())
-            .add_system_set(
-                SystemSet::on_update(AppState::GameRunning)
-                    .with_system(belt_advance_items_system.system())
-                    .with_system(null_sink_system.system())
-                    .with_system(random_item_generator_system.system())
-                    .with_system(merger_system.system()),
+        if app.world().get_resource::<ItemRegistry>().is_none() {
+            app.insert_resource(ItemRegistry::load_default());
+        }
+
+        app.insert_resource(BeltLines::default())
+            .insert_resource(AwakeChunks::default())
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                belt_line_system
+                    .system()
+                    .label("belt_line")
+                    .after("output_stuff_hookup"),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, belt_line_dirty_system.system())
+            // one fixed order so every tick does exactly the same
+            .add_system_set_to_stage(
+                SimulationStage,
+                SystemSet::new()
+                    .with_system(chunk_activity_system.system().label("chunks"))
+                    .with_system(
+                        belt_input_system
+                            .system()
+                            .label("belt_input")
+                            .after("chunks"),
+                    )
+                    .with_system(
+                        belt_advance_items_system
+                            .system()
+                            .label("belt_advance")
+                            .after("belt_input"),
+                    )
+                    .with_system(
+                        null_sink_system
+                            .system()
+                            .label("sink")
+                            .after("belt_advance"),
+                    )
+                    .with_system(
+                        random_item_generator_system
+                            .system()
+                            .label("generator")
+                            .after("sink"),
+                    )
+                    .with_system(merger_system.system().label("merger").after("generator"))
+                    .with_system(splitter_system.system().label("splitter").after("merger")),
             );
     }
 }
@@ -1,12 +1,24 @@
-use std::slice::{Iter, IterMut};
-
 use bevy::{math::vec3, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AwakeChunks, BeltLine, CompassDir, FusedInto, ItemId, ItemRegistry, MapCache, MapPos,
+    TICK_SECONDS, TILE_SIZE,
+};
+
+/// distance of a lane to the middle of its belt
+pub const LANE_OFFSET: f32 = 0.2 * TILE_SIZE;
 
 ///////////////////////////////////////////////////////////////////////////////
 
 #[derive(Debug)]
 pub struct ItemInput {
-    space: f32,
+    /// per lane, free space from the start of the belt to the first item
+    space: [f32; 2],
+    /// per lane, free space around the middle of the belt for side loading
+    side_space: [f32; 2],
+    /// pos of the middle of the belt where side loaded items are placed
+    side_pos: f32,
     space_padding: bool,
     items: Vec<BeltItem>,
     capacity: usize,
@@ -15,7 +27,9 @@ pub struct ItemInput {
 impl ItemInput {
     pub fn new(capacity: usize) -> Self {
         Self {
-            space: f32::INFINITY,
+            space: [f32::INFINITY; 2],
+            side_space: [f32::INFINITY; 2],
+            side_pos: 0.0,
             space_padding: true,
             capacity,
             items: Vec::new(),
@@ -26,6 +40,37 @@ impl ItemInput {
         self.capacity.saturating_sub(self.items.len())
     }
 
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// the space on a belt of `length` without items
+    pub fn reset_space(&mut self, length: f32, side_loadable: bool) {
+        self.space = [length; 2];
+        self.side_space = if side_loadable {
+            [f32::INFINITY; 2]
+        } else {
+            [0.0; 2]
+        };
+        self.side_pos = 0.5 * length;
+    }
+
+    /// `true` if `reset_space` would not change anything
+    pub fn is_reset(&self, length: f32, side_loadable: bool) -> bool {
+        let side_space = if side_loadable { f32::INFINITY } else { 0.0 };
+        self.space == [length; 2]
+            && self.side_space == [side_space; 2]
+            && self.side_pos == 0.5 * length
+    }
+
+    pub fn items(&self) -> &[BeltItem] {
+        &self.items
+    }
+
     pub fn clear_items(&mut self) {
         self.items.clear();
     }
@@ -45,32 +90,73 @@ impl ItemInput {
 
 ///////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Debug)]
+/// left and right as seen in the direction of travel
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lane {
+    Left,
+    Right,
+}
+
+impl Lane {
+    pub const ALL: [Lane; 2] = [Lane::Left, Lane::Right];
+
+    pub fn index(&self) -> usize {
+        match self {
+            Lane::Left => 0,
+            Lane::Right => 1,
+        }
+    }
+
+    /// the lane which is reached when entering a belt,
+    /// which travels in `travel` direction, from the `from` side
+    pub fn side_loaded(travel: CompassDir, from: CompassDir) -> Option<Lane> {
+        if from == travel.left() {
+            Some(Lane::Left)
+        } else if from == travel.right() {
+            Some(Lane::Right)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Lane {
+    fn default() -> Self {
+        Lane::Right
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BeltItem {
     pub pos: f32,
-    pub item: Item,
+    pub item: ItemId,
+    pub lane: Lane,
 }
 
 impl BeltItem {
-    pub fn new(pos: f32, item: Item) -> Self {
-        Self { pos, item }
-    }
-
-    pub fn red(pos: f32) -> Self {
-        Self::new(pos, Item::Red)
+    pub fn new(pos: f32, item: ItemId) -> Self {
+        Self {
+            pos,
+            item,
+            lane: Lane::default(),
+        }
     }
 
-    pub fn green(pos: f32) -> Self {
-        Self::new(pos, Item::Green)
+    pub fn on_lane(mut self, lane: Lane) -> Self {
+        self.lane = lane;
+        self
     }
 
-    pub fn padding(&self) -> f32 {
-        10.0
+    /// half of the space the item takes on a belt
+    pub fn padding(&self, registry: &ItemRegistry) -> f32 {
+        registry.padding(self.item)
     }
 }
 
 impl std::ops::Deref for BeltItem {
-    type Target = Item;
+    type Target = ItemId;
 
     fn deref(&self) -> &Self::Target {
         &self.item
@@ -79,6 +165,7 @@ impl std::ops::Deref for BeltItem {
 
 ///////////////////////////////////////////////////////////////////////////////
 
+#[derive(Clone, Copy, Debug)]
 pub struct BeltSegment {
     pub start: Vec3,
     pub end: Vec3,
@@ -95,17 +182,199 @@ impl BeltSegment {
 
 ///////////////////////////////////////////////////////////////////////////////
 
-#[derive(Clone, Copy, Debug)]
-pub enum Item {
-    Red,
-    Green,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BeltTier {
+    Basic,
+    Fast,
+    Express,
 }
 
-impl Item {
-    pub fn random() -> Self {
-        use Item::*;
-        let items = [Red, Green];
-        items[fastrand::usize(0..items.len())]
+impl BeltTier {
+    /// units per second an item travels on a belt of this tier
+    pub fn speed(&self) -> f32 {
+        use BeltTier::*;
+        match self {
+            Basic => 50.0,
+            Fast => 100.0,
+            Express => 150.0,
+        }
+    }
+}
+
+impl Default for BeltTier {
+    fn default() -> Self {
+        BeltTier::Basic
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// gaps which are this close to their minimum count as compressed
+const COMPRESSED_EPSILON: f32 = 0.001;
+
+#[derive(Clone, Debug)]
+struct LaneItem {
+    item: ItemId,
+    /// distance to the item in front, or for the front item to the end of the belt
+    gap: f32,
+}
+
+/// Items of one lane stored as gaps between each other, from the end of the belt
+/// to its start. While items move only the first gap with room shrinks, the others
+/// move along unchanged. Items which are already pushed together behind the front item
+/// are counted as `compressed` and are skipped, so a blocked full lane costs nothing.
+#[derive(Clone, Debug, Default)]
+pub struct BeltLane {
+    items: Vec<LaneItem>,
+    /// the items after the front item up to this count are pushed together
+    compressed: usize,
+    /// sum of all gaps, the distance of the last item to the end of the belt
+    back: f32,
+}
+
+impl BeltLane {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// items from the front to the back with their pos on a belt of `length`
+    pub fn items(&self, length: f32, lane: Lane) -> impl Iterator<Item = BeltItem> + '_ {
+        self.items.iter().scan(length, move |pos, it| {
+            *pos -= it.gap;
+            Some(BeltItem::new(*pos, it.item).on_lane(lane))
+        })
+    }
+
+    /// the item closest to the start of the belt
+    pub fn last(&self, length: f32, lane: Lane) -> Option<BeltItem> {
+        self.items
+            .last()
+            .map(|it| BeltItem::new(length - self.back, it.item).on_lane(lane))
+    }
+
+    pub fn front_item(&self) -> Option<ItemId> {
+        self.items.first().map(|it| it.item)
+    }
+
+    pub fn insert(&mut self, item: &BeltItem, length: f32) {
+        let last_pos = length - self.back;
+
+        // usually items are put at the start of the belt, behind all others
+        if self.items.is_empty() || item.pos <= last_pos {
+            self.items.push(LaneItem {
+                item: item.item,
+                gap: last_pos.min(length) - item.pos,
+            });
+            self.back = length - item.pos;
+            return;
+        }
+
+        let mut pos = length;
+        for index in 0..self.items.len() {
+            let next_pos = pos - self.items[index].gap;
+            if next_pos < item.pos {
+                self.items[index].gap = item.pos - next_pos;
+                self.items.insert(
+                    index,
+                    LaneItem {
+                        item: item.item,
+                        gap: pos - item.pos,
+                    },
+                );
+                self.compressed = self.compressed.min(index.saturating_sub(1));
+                return;
+            }
+            pos = next_pos;
+        }
+    }
+
+    pub fn take_items(&mut self, length: f32, lane: Lane) -> Vec<BeltItem> {
+        let items = self.items(length, lane).collect();
+        *self = Self::default();
+        items
+    }
+
+    /// move the items forward by `advance`, the front item can move up to
+    /// `front_limit` past the end of the belt, or has to stop before it if negative
+    pub fn advance(&mut self, advance: f32, front_limit: f32, registry: &ItemRegistry) {
+        let mut remaining = advance;
+
+        if let Some(front) = self.items.first_mut() {
+            let shrink = remaining.min(front.gap + front_limit).max(0.0);
+            front.gap -= shrink;
+            self.back -= shrink;
+            remaining -= shrink;
+        }
+
+        let mut index = 1 + self.compressed;
+        while remaining > 0.0 && index < self.items.len() {
+            let shrink = remaining
+                .min(self.items[index].gap - self.min_gap(index, registry))
+                .max(0.0);
+            self.items[index].gap -= shrink;
+            self.back -= shrink;
+            remaining -= shrink;
+            index += 1;
+        }
+
+        while 1 + self.compressed < self.items.len()
+            && self.items[1 + self.compressed].gap
+                <= self.min_gap(1 + self.compressed, registry) + COMPRESSED_EPSILON
+        {
+            self.compressed += 1;
+        }
+    }
+
+    /// the front item with its pos past the end, if it moved past the end
+    pub fn front_past_end(&self, lane: Lane) -> Option<BeltItem> {
+        self.items
+            .first()
+            .filter(|it| it.gap < 0.0)
+            .map(|it| BeltItem::new(-it.gap, it.item).on_lane(lane))
+    }
+
+    pub fn pop_front(&mut self) {
+        self.remove(0);
+    }
+
+    /// the item behind takes over the gap of the removed item
+    pub fn remove(&mut self, index: usize) -> Option<ItemId> {
+        if index >= self.items.len() {
+            return None;
+        }
+
+        let removed = self.items.remove(index);
+        if let Some(next) = self.items.get_mut(index) {
+            next.gap += removed.gap;
+        } else {
+            self.back -= removed.gap;
+        }
+        if self.items.is_empty() {
+            self.back = 0.0;
+        }
+        self.compressed = if index == 0 {
+            self.compressed.saturating_sub(1)
+        } else {
+            self.compressed.min(index - 1)
+        };
+        Some(removed.item)
+    }
+
+    pub fn stop_front_at_end(&mut self) {
+        if let Some(front) = self.items.first_mut() {
+            if front.gap < 0.0 {
+                self.back -= front.gap;
+                front.gap = 0.0;
+            }
+        }
+    }
+
+    fn min_gap(&self, index: usize, registry: &ItemRegistry) -> f32 {
+        registry.padding(self.items[index - 1].item) + registry.padding(self.items[index].item)
     }
 }
 
@@ -113,17 +382,41 @@ impl Item {
 
 pub struct Belt {
     pub segments: Vec<BeltSegment>,
-    pub items: Vec<BeltItem>,
+    /// items per lane, indexed by `Lane::index`
+    pub lanes: [BeltLane; 2],
     pub output: Option<Entity>,
+    /// `Some` if this belt loads onto the side of its output, only onto that lane
+    pub output_lane: Option<Lane>,
+    pub tier: BeltTier,
 }
 
 impl Belt {
-    pub fn items(&self) -> Iter<BeltItem> {
-        self.items.iter()
+    /// units per second items travel on it
+    pub fn speed(&self) -> f32 {
+        self.tier.speed()
+    }
+
+    pub fn with_items<T: IntoIterator<Item = BeltItem>>(mut self, items: T) -> Self {
+        for item in items {
+            self.add_item(item);
+        }
+        self
+    }
+
+    /// all items with their pos, this walks all gaps of the belt
+    pub fn items(&self) -> impl Iterator<Item = BeltItem> + '_ {
+        let length = self.total_length();
+        Lane::ALL
+            .iter()
+            .flat_map(move |lane| self.lane_items(*lane, length))
+    }
+
+    pub fn lane_items(&self, lane: Lane, length: f32) -> impl Iterator<Item = BeltItem> + '_ {
+        self.lanes[lane.index()].items(length, lane)
     }
 
-    pub fn items_mut(&mut self) -> IterMut<BeltItem> {
-        self.items.iter_mut()
+    pub fn lane(&self, lane: Lane) -> &BeltLane {
+        &self.lanes[lane.index()]
     }
 
     pub fn segments(&self) -> impl Iterator<Item = &BeltSegment> {
@@ -135,15 +428,23 @@ impl Belt {
     }
 
     pub fn add_item(&mut self, item: BeltItem) {
-        let index = self
-            .items
-            .binary_search_by(|other| other.pos.total_cmp(&item.pos))
-            .map_or_else(|i| i, |i| i);
-        self.items.insert(index, item);
+        let length = self.total_length();
+        self.lanes[item.lane.index()].insert(&item, length);
     }
 
-    pub fn pass_on(&mut self, count: usize) -> Vec<BeltItem> {
-        self.items.split_off(self.items.len() - count)
+    /// removes the item at `index` of `lane`, counted from the front
+    pub fn remove_item(&mut self, lane: Lane, index: usize) -> Option<ItemId> {
+        self.lanes[lane.index()].remove(index)
+    }
+
+    /// removes all items, they keep their pos
+    pub fn take_items(&mut self) -> Vec<BeltItem> {
+        let length = self.total_length();
+        let mut items = Vec::new();
+        for lane in Lane::ALL.iter() {
+            items.extend(self.lanes[lane.index()].take_items(length, *lane));
+        }
+        items
     }
 
     /// go through each segment, accumulate segment lengths,
@@ -175,92 +476,136 @@ impl Belt {
         }
     }
 
+    /// like `location_on_path` but moved sideways onto the lane
+    pub fn location_on_lane(&self, pos: f32, lane: Lane) -> (Vec3, Vec3) {
+        let (location, dir) = self.location_on_path(pos);
+        let left = vec3(-dir.y, dir.x, 0.0);
+        let offset = match lane {
+            Lane::Left => LANE_OFFSET,
+            Lane::Right => -LANE_OFFSET,
+        };
+        (location + offset * left, dir)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(BeltLane::is_empty)
+    }
+
     pub fn total_length(&self) -> f32 {
         self.segments
             .iter()
             .fold(0.0, |acc, seg| acc + seg.start.distance(seg.end))
     }
 
-    pub fn is_space(&self, item: &BeltItem) -> bool {
-        if let Some(first) = self.items.first() {
-            item.padding() <= first.pos - first.padding()
+    pub fn is_space(&self, item: &BeltItem, registry: &ItemRegistry) -> bool {
+        let length = self.total_length();
+        if let Some(last) = self.lane(item.lane).last(length, item.lane) {
+            item.padding(registry) <= last.pos - last.padding(registry)
         } else {
             true
         }
     }
 }
 
+/// Belts in sleeping chunks are not looked at, belts without a `MapPos` never sleep.
 pub fn belt_advance_items_system(
-    mut belts: Query<&mut Belt>,
+    mut belts: Query<&mut Belt, (With<MapPos>, Without<FusedInto>)>,
+    mut unmapped: Query<&mut Belt, (Without<MapPos>, Without<FusedInto>)>,
     mut item_inputs: Query<&mut ItemInput>,
-    time: Res<Time>,
+    registry: Res<ItemRegistry>,
+    awake: Res<AwakeChunks>,
+    map: Option<Res<MapCache>>,
 ) {
-    let time = time.delta_seconds();
+    let registry = &*registry;
+
+    if let Some(map) = map.as_deref() {
+        for entity in awake.belts(map) {
+            if let Ok(mut belt) = belts.get_mut(entity) {
+                advance_belt(&mut belt, &mut item_inputs, registry);
+            }
+        }
+    }
+    for mut belt in unmapped.iter_mut() {
+        advance_belt(&mut belt, &mut item_inputs, registry);
+    }
+}
 
-    for mut belt in belts.iter_mut() {
-        let speed = 50.0;
-        let advance = speed * time;
+fn advance_belt(belt: &mut Belt, item_inputs: &mut Query<&mut ItemInput>, registry: &ItemRegistry) {
+    let advance = belt.speed() * TICK_SECONDS;
+    let output_lane = belt.output_lane;
 
-        let total_length = belt.total_length();
-        let mut next_stop = if belt.output.is_some() {
-            NextStop::Output
-        } else {
-            NextStop::End
+    let mut item_input = belt.output.and_then(|e| item_inputs.get_mut(e).ok());
+
+    for (lane, items) in Lane::ALL.iter().copied().zip(belt.lanes.iter_mut()) {
+        let (item_input, front) = match (item_input.as_mut(), items.front_item()) {
+            (Some(item_input), Some(front)) => (item_input, front),
+            (None, _) => {
+                items.advance(advance, 0.0, registry);
+                continue;
+            }
+            (Some(_), None) => continue,
         };
 
-        let mut item_input = belt.output.and_then(|e| item_inputs.get_mut(e).ok());
-
-        for i in (0..belt.items.len()).rev() {
-            match next_stop {
-                NextStop::End => {
-                    let item = &mut belt.items[i];
-                    item.pos = f32::min(total_length, item.pos + advance);
-                    next_stop = NextStop::Item(item.pos - item.padding());
-                }
-                NextStop::Item(stop) => {
-                    let item = &mut belt.items[i];
-                    item.pos = (stop - item.padding()).min(item.pos + advance);
-                    next_stop = NextStop::Item(item.pos - item.padding());
-                }
-                NextStop::Output => {
-                    let item = &mut belt.items[i];
-                    let item_input = item_input.as_mut().unwrap();
-
-                    let size = if item_input.space_padding {
-                        item.padding()
-                    } else {
-                        0.0
-                    };
-
-                    item.pos = (item.pos + advance).min(total_length + item_input.space - size);
-
-                    if item.pos > total_length {
-                        item.pos -= total_length;
-
-                        if try_push_item_to_input(item, item_input) {
-                            belt.items.remove(i);
-                        } else {
-                            item.pos = total_length;
-                            next_stop = NextStop::Item(item.pos - item.padding());
-                        }
-                    } else {
-                        next_stop = NextStop::Item(item.pos - item.padding());
-                    }
-                }
+        let front_limit = output_limit(front, lane, output_lane, item_input, registry);
+        items.advance(advance, front_limit, registry);
+
+        while let Some(mut item) = items.front_past_end(lane) {
+            let pushed = match output_lane {
+                None => try_push_item_to_input(&mut item, item_input, registry),
+                Some(side) => try_side_load_item_to_input(&mut item, side, item_input, registry),
+            };
+
+            if pushed {
+                items.pop_front();
+            } else {
+                items.stop_front_at_end();
+                break;
             }
         }
     }
 }
 
-pub fn try_push_item_to_input(item: &mut BeltItem, input: &mut ItemInput) -> bool {
+/// how far the front item can move past the end of the belt into the output
+fn output_limit(
+    front: ItemId,
+    lane: Lane,
+    output_lane: Option<Lane>,
+    input: &ItemInput,
+    registry: &ItemRegistry,
+) -> f32 {
     let size = if input.space_padding {
-        item.padding()
+        registry.padding(front)
     } else {
         0.0
     };
 
-    if size <= input.space && input.has_capacity() > 0 {
-        item.pos = item.pos.min(input.space - size);
+    let limit = match output_lane {
+        None => input.space[lane.index()] - size,
+        Some(side) if size <= input.side_space[side.index()] => f32::INFINITY,
+        Some(_) => 0.0,
+    };
+
+    if input.has_capacity() > 0 {
+        limit
+    } else {
+        limit.min(0.0)
+    }
+}
+
+pub fn try_push_item_to_input(
+    item: &mut BeltItem,
+    input: &mut ItemInput,
+    registry: &ItemRegistry,
+) -> bool {
+    let size = if input.space_padding {
+        item.padding(registry)
+    } else {
+        0.0
+    };
+    let lane = item.lane.index();
+
+    if size <= input.space[lane] && input.has_capacity() > 0 {
+        item.pos = item.pos.min(input.space[lane] - size);
         input.items.insert(0, item.clone());
         true
     } else {
@@ -269,23 +614,242 @@ pub fn try_push_item_to_input(item: &mut BeltItem, input: &mut ItemInput) -> boo
     }
 }
 
-enum NextStop {
-    End,
-    Item(f32),
-    Output,
+/// like `try_push_item_to_input` but puts the item onto the middle of `lane`
+pub fn try_side_load_item_to_input(
+    item: &mut BeltItem,
+    lane: Lane,
+    input: &mut ItemInput,
+    registry: &ItemRegistry,
+) -> bool {
+    let size = if input.space_padding {
+        item.padding(registry)
+    } else {
+        0.0
+    };
+    let index = lane.index();
+
+    if size <= input.side_space[index] && input.has_capacity() > 0 {
+        item.pos = input.side_pos;
+        item.lane = lane;
+        input.side_space[index] = 0.0;
+        input.space[index] = input.space[index].min(item.pos - size);
+        input.items.insert(0, item.clone());
+        true
+    } else {
+        item.pos = 0.0;
+        false
+    }
 }
 
-pub fn belt_input_system(mut belts: Query<(Entity, &mut Belt, &mut ItemInput)>) {
-    // transfer items from ItemInput to belt and update ItemInput space
-    for (_, mut belt, mut item_input) in belts.iter_mut() {
-        for item in item_input.items.drain(0..).rev() {
-            belt.items.insert(0, item);
+/// Like `belt_advance_items_system` only belts in awake chunks or without a `MapPos` run.
+pub fn belt_input_system(
+    mut belts: Query<(&mut Belt, &mut ItemInput, Option<&BeltLine>), With<MapPos>>,
+    mut unmapped: Query<(&mut Belt, &mut ItemInput, Option<&BeltLine>), Without<MapPos>>,
+    registry: Res<ItemRegistry>,
+    awake: Res<AwakeChunks>,
+    map: Option<Res<MapCache>>,
+) {
+    if let Some(map) = map.as_deref() {
+        for entity in awake.belts(map) {
+            if let Ok((mut belt, mut item_input, line)) = belts.get_mut(entity) {
+                take_input(&mut belt, &mut item_input, line, &registry);
+            }
         }
+    }
+    for (mut belt, mut item_input, line) in unmapped.iter_mut() {
+        take_input(&mut belt, &mut item_input, line, &registry);
+    }
+}
+
+/// transfer items from ItemInput to belt and update ItemInput space
+fn take_input(
+    belt: &mut Belt,
+    item_input: &mut ItemInput,
+    line: Option<&BeltLine>,
+    registry: &ItemRegistry,
+) {
+    for item in item_input.items.drain(..) {
+        belt.add_item(item);
+    }
+
+    let total_length = belt.total_length();
+    let middle = 0.5 * total_length;
+    item_input.side_pos = middle;
 
-        item_input.space = if let Some(first) = belt.items.first() {
-            first.pos - first.padding()
+    for lane in Lane::ALL.iter().copied() {
+        item_input.space[lane.index()] = match belt.lane(lane).last(total_length, lane) {
+            Some(last) => last.pos - last.padding(registry),
+            None => total_length,
+        };
+
+        // lines are never side loaded and looking for space would walk all their items
+        item_input.side_space[lane.index()] = if line.is_some() {
+            0.0
         } else {
-            belt.total_length()
+            belt.lane_items(lane, total_length)
+                .fold(f32::INFINITY, |space, it| {
+                    space.min((it.pos - middle).abs() - it.padding(registry))
+                })
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: ItemId = ItemId(0);
+    const LENGTH: f32 = 100.0;
+
+    /// items put on in this order, the default items are 20 apart when pushed together
+    fn lane_with(positions: &[f32]) -> BeltLane {
+        let mut lane = BeltLane::default();
+        for pos in positions {
+            lane.insert(&BeltItem::new(*pos, RED), LENGTH);
+        }
+        lane
+    }
+
+    fn positions(lane: &BeltLane) -> Vec<f32> {
+        lane.items(LENGTH, Lane::Left).map(|it| it.pos).collect()
+    }
+
+    /// the items counted as `compressed` are pushed together and `back` is the last item
+    fn assert_consistent(lane: &BeltLane, registry: &ItemRegistry) {
+        assert!(lane.compressed < lane.len().max(1));
+        for index in 1..=lane.compressed.min(lane.len().saturating_sub(1)) {
+            assert!(lane.items[index].gap <= lane.min_gap(index, registry) + COMPRESSED_EPSILON);
+        }
+        assert_eq!(
+            positions(lane).last().copied(),
+            lane.last(LENGTH, Lane::Left).map(|it| it.pos)
+        );
+    }
+
+    #[test]
+    fn free_items_move_together() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[70.0, 40.0, 10.0]);
+        assert_consistent(&lane, &registry);
+
+        lane.advance(5.0, 0.0, &registry);
+        assert_eq!(vec![75.0, 45.0, 15.0], positions(&lane));
+        assert_eq!(0, lane.compressed);
+        assert_consistent(&lane, &registry);
+    }
+
+    #[test]
+    fn blocked_items_are_pushed_together() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[95.0, 70.0, 30.0]);
+
+        lane.advance(10.0, 0.0, &registry);
+        assert_eq!(vec![100.0, 80.0, 40.0], positions(&lane));
+        assert_eq!(1, lane.compressed);
+        assert_consistent(&lane, &registry);
+        lane.advance(25.0, 0.0, &registry);
+        assert_eq!(vec![100.0, 80.0, 60.0], positions(&lane));
+        assert_eq!(2, lane.compressed);
+        assert_consistent(&lane, &registry);
+
+        // fully compressed nothing moves
+        lane.advance(10.0, 0.0, &registry);
+        assert_eq!(vec![100.0, 80.0, 60.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+    }
+
+    #[test]
+    fn the_front_item_moves_past_the_end_and_back() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[100.0, 80.0, 60.0]);
+        lane.advance(1.0, 0.0, &registry);
+        assert_eq!(2, lane.compressed);
+        assert!(lane.front_past_end(Lane::Left).is_none());
+
+        lane.advance(10.0, 5.0, &registry);
+        assert_eq!(vec![105.0, 85.0, 65.0], positions(&lane));
+        let past = lane.front_past_end(Lane::Left).unwrap();
+        assert_eq!((5.0, RED), (past.pos, past.item));
+        assert_consistent(&lane, &registry);
+
+        lane.stop_front_at_end();
+        assert!(lane.front_past_end(Lane::Left).is_none());
+        assert_eq!(vec![100.0, 80.0, 60.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+
+        // handed on, the items behind keep their place
+        lane.advance(10.0, 5.0, &registry);
+        lane.pop_front();
+        assert_eq!(vec![85.0, 65.0], positions(&lane));
+        assert_eq!(1, lane.compressed);
+        assert_consistent(&lane, &registry);
+    }
+
+    #[test]
+    fn insert_in_the_middle_and_at_the_back() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[90.0, 30.0]);
+        lane.insert(&BeltItem::new(60.0, RED), LENGTH);
+        assert_eq!(vec![90.0, 60.0, 30.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+        lane.insert(&BeltItem::new(95.0, RED), LENGTH);
+        assert_eq!(vec![95.0, 90.0, 60.0, 30.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+        lane.insert(&BeltItem::new(5.0, RED), LENGTH);
+        assert_eq!(vec![95.0, 90.0, 60.0, 30.0, 5.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+    }
+
+    #[test]
+    fn insert_into_a_compressed_lane() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[100.0, 80.0, 60.0, 40.0]);
+        lane.advance(1.0, 0.0, &registry);
+        assert_eq!(3, lane.compressed);
+
+        lane.insert(&BeltItem::new(70.0, RED), LENGTH);
+        assert_eq!(vec![100.0, 80.0, 70.0, 60.0, 40.0], positions(&lane));
+        assert_eq!(1, lane.compressed);
+        assert_consistent(&lane, &registry);
+
+        lane.insert(&BeltItem::new(20.0, RED), LENGTH);
+        assert_eq!(1, lane.compressed);
+        assert_consistent(&lane, &registry);
+    }
+
+    #[test]
+    fn remove_in_the_middle_merges_the_gap() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[90.0, 60.0, 30.0]);
+        assert_eq!(Some(RED), lane.remove(1));
+        assert_eq!(vec![90.0, 30.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+
+        assert_eq!(Some(RED), lane.remove(1));
+        assert_eq!(vec![90.0], positions(&lane));
+        assert_consistent(&lane, &registry);
+        assert_eq!(None, lane.remove(1));
+
+        assert_eq!(Some(RED), lane.remove(0));
+        assert!(lane.is_empty());
+        assert_consistent(&lane, &registry);
+    }
+
+    #[test]
+    fn remove_from_a_compressed_lane() {
+        let registry = ItemRegistry::default();
+        let mut lane = lane_with(&[100.0, 80.0, 60.0, 40.0]);
+        lane.advance(1.0, 0.0, &registry);
+        assert_eq!(3, lane.compressed);
+
+        lane.remove(2);
+        assert_eq!(vec![100.0, 80.0, 40.0], positions(&lane));
+        assert_eq!(1, lane.compressed);
+        assert_consistent(&lane, &registry);
+
+        lane.advance(30.0, 0.0, &registry);
+        assert_eq!(vec![100.0, 80.0, 60.0], positions(&lane));
+        assert_eq!(2, lane.compressed);
+        assert_consistent(&lane, &registry);
+    }
+}
@@ -29,7 +29,11 @@ fn debug_draw_item_things_system(
     }
 }
 
-fn debug_draw_belt_system(mut lines: ResMut<DebugLines>, belts: Query<&Belt>) {
+fn debug_draw_belt_system(
+    mut lines: ResMut<DebugLines>,
+    belts: Query<&Belt>,
+    registry: Res<ItemRegistry>,
+) {
     // draw belt segments
     // draw belt items
     // NOTE that debug lines don't draw well over each other, but increase duration
@@ -48,10 +52,10 @@ fn debug_draw_belt_system(mut lines: ResMut<DebugLines>, belts: Query<&Belt>) {
         }
 
         for item in belt.items() {
-            let (pos, dir) = belt.location_on_path(item.pos);
+            let (pos, dir) = belt.location_on_lane(item.pos, item.lane);
             let start = pos - 0.5 * dir;
             let end = pos + 0.5 * dir;
-            lines.line_colored(start, end, 0.02, item.color());
+            lines.line_colored(start, end, 0.02, registry.color(item.item));
         }
     }
 }
@@ -59,11 +63,14 @@ fn debug_draw_belt_system(mut lines: ResMut<DebugLines>, belts: Query<&Belt>) {
 fn debug_belt_path_place_random_items_system(
     trigger: Res<Input<KeyCode>>,
     mut belts: Query<&mut Belt>,
+    mut rng: ResMut<SimRng>,
+    registry: Res<ItemRegistry>,
 ) {
     if trigger.just_pressed(KeyCode::R) {
         println!("debug_belt_path_place_random_items_system");
         for mut belt in belts.iter_mut() {
-            let item = BeltItem::new(belt.total_length() * fastrand::f32(), Item::random());
+            let pos = belt.total_length() * rng.f32();
+            let item = BeltItem::new(pos, registry.random(&mut rng));
             belt.add_item(item);
         }
     }
@@ -27,14 +27,16 @@ fn draw_belt_system(
     mut draw_items: Local<DrawItems>,
     mut cmds: Commands,
     item_atlas: Res<ItemAtlasHandle>,
+    registry: Res<ItemRegistry>,
 ) {
     let mut index = 0;
 
     for belt in belts.iter() {
         for item in belt.items() {
-            let item: &BeltItem = item;
-            let (pos, _dir) = belt.location_on_path(item.pos) as (Vec3, Vec3);
+            let item: BeltItem = item;
+            let (pos, _dir) = belt.location_on_lane(item.pos, item.lane) as (Vec3, Vec3);
             let pos = vec3(pos.x, pos.y, 0.1);
+            let def = registry.get(item.item);
 
             if index >= draw_items.entities.len() {
                 let entity = cmds
@@ -46,8 +48,8 @@ fn draw_belt_system(
                             is_transparent: true,
                         },
                         sprite: TextureAtlasSprite {
-                            index: 0,
-                            color: item.color(),
+                            index: def.sprite_index,
+                            color: def.color,
                             ..Default::default()
                         },
                         ..Default::default()
@@ -55,8 +57,11 @@ fn draw_belt_system(
                     .id();
                 draw_items.entities.push(entity);
             } else if let Ok((mut trans, mut sprite)) = sprite.get_mut(draw_items.entities[index]) {
-                if sprite.color != item.color() {
-                    sprite.color = item.color();
+                if sprite.color != def.color {
+                    sprite.color = def.color;
+                }
+                if sprite.index != def.sprite_index {
+                    sprite.index = def.sprite_index;
                 }
                 if trans.translation != pos {
                     trans.translation = pos;
@@ -102,9 +107,14 @@ fn belt_sprite_animation_system(
 ) {
     use CompassDir::*;
     let time = time.seconds_since_startup();
-    let anim_col = (time.fract() * 8.0) as u32;
+    let basic_speed = BeltTier::Basic.speed() as f64;
+
+    for (entity, anim, belt, input, output, mut sprite) in belts.iter_mut() {
+        // a basic belt runs through its 8 animation frames once per second,
+        // faster belts scale the frame rate with their speed
+        let rate = belt.speed() as f64 / basic_speed;
+        let anim_col = ((time * rate).fract() * 8.0) as u32;
 
-    for (entity, anim, _belt, input, output, mut sprite) in belts.iter_mut() {
         let mut new_anim: BeltSpriteAnimation = anim.cloned().unwrap_or_else(|| {
             let (row, flip_x, flip_y, backwards) =
                 match (input.dir, output.outputs.first().unwrap().dir) {
@@ -143,16 +153,3 @@ fn belt_sprite_animation_system(
         cmds.entity(entity).insert(new_anim);
     }
 }
-
-pub trait GetColor {
-    fn color(&self) -> Color;
-}
-
-impl GetColor for Item {
-    fn color(&self) -> Color {
-        match self {
-            Item::Red => COLOR_C2,
-            Item::Green => COLOR_P2,
-        }
-    }
-}
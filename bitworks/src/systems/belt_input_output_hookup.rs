@@ -1,20 +1,47 @@
 use bevy::{prelude::*, utils::HashSet};
 
-use crate::{Belt, CompassDir, MapCache, MapPos, Merger, RandomItemGenerator};
+use crate::{
+    underground_belt_pairing_system, Assembler, Belt, Chest, CompassDir, Lane, MapCache, MapLayer,
+    MapPos, Merger, MiningDrill, RandomItemGenerator, Splitter,
+};
 
 pub struct SingleInput {
     pub pos: MapPos,
     pub dir: CompassDir,
+    /// accepts outputs from the left and right side too, see `Lane::side_loaded`
+    pub side_loading: bool,
+}
+
+impl SingleInput {
+    pub fn with_side_loading(mut self) -> Self {
+        self.side_loading = true;
+        self
+    }
+
+    /// the lane an output coming `from` loads onto, if it is a side of this input
+    pub fn side_lane(&self, from: CompassDir) -> Option<Lane> {
+        if self.side_loading {
+            Lane::side_loaded(self.dir.opposite(), from)
+        } else {
+            None
+        }
+    }
 }
 
 pub fn input(pos: MapPos, dir: CompassDir) -> SingleInput {
-    SingleInput { pos, dir }
+    SingleInput {
+        pos,
+        dir,
+        side_loading: false,
+    }
 }
 
 pub struct SingleOutput {
     pub pos: MapPos,
     pub dir: CompassDir,
     pub entity: Option<Entity>,
+    /// `Some` if the output is side loading onto this lane of the input
+    pub lane: Option<Lane>,
 }
 
 pub struct MultipleOutputs {
@@ -30,6 +57,7 @@ impl MultipleOutputs {
                     pos: *pos,
                     dir: *dir,
                     entity: None,
+                    lane: None,
                 })
                 .collect(),
         }
@@ -42,6 +70,7 @@ pub fn output<P: Into<MapPos>>(pos: P, dir: CompassDir) -> MultipleOutputs {
             pos: pos.into(),
             dir,
             entity: None,
+            lane: None,
         }],
     }
 }
@@ -60,7 +89,14 @@ impl Plugin for BeltInputOutputHookupPlugin {
         )
         .add_system_to_stage(
             CoreStage::PreUpdate,
-            output_item_stuff_hookup_system.system().after("io_hookup"),
+            output_item_stuff_hookup_system
+                .system()
+                .label("output_stuff_hookup")
+                .after("io_hookup"),
+        )
+        .add_system_to_stage(
+            CoreStage::PreUpdate,
+            underground_belt_pairing_system.system().before("io_hookup"),
         );
     }
 }
@@ -68,6 +104,7 @@ impl Plugin for BeltInputOutputHookupPlugin {
 #[derive(Default)]
 struct DebugIOHookupSystem {
     hookup: Vec<(Entity, Entity)>,
+    side_load: Vec<(Entity, Entity, Lane)>,
     wrong_dir: Vec<(Entity, CompassDir, CompassDir)>,
     no_input: Vec<(Entity, MapPos)>,
     none_at: Vec<(Entity, MapPos)>,
@@ -80,6 +117,9 @@ impl DebugIOHookupSystem {
         for it in self.hookup.iter().filter(|e| set.insert(e.0)) {
             debug!("check {:?} hookup {:?}", it.0, it.1);
         }
+        for it in self.side_load.iter().filter(|e| set.insert(e.0)) {
+            debug!("check {:?} side load {:?} on {:?}", it.0, it.1, it.2);
+        }
         for it in self.wrong_dir.iter().filter(|e| set.insert(e.0)) {
             debug!("check {:?} wrong dir {:?} to {:?}", it.0, it.1, it.2);
         }
@@ -107,12 +147,18 @@ fn input_output_hookup_system(
 
             if output.entity.is_some() {
                 continue;
-            } else if let Some(input_entity) = map.at(&other_pos) {
+            } else if let Some(input_entity) = map.at(MapLayer::Belts, &other_pos) {
                 if let Some(input) = inputs.get_component::<SingleInput>(input_entity).ok() {
                     if input.dir == output.dir.opposite() {
                         outputs.outputs[i].entity = Some(input_entity);
+                        outputs.outputs[i].lane = None;
 
                         debug.hookup.push((o_entity, input_entity));
+                    } else if let Some(lane) = input.side_lane(output.dir.opposite()) {
+                        outputs.outputs[i].entity = Some(input_entity);
+                        outputs.outputs[i].lane = Some(lane);
+
+                        debug.side_load.push((o_entity, input_entity, lane));
                     } else {
                         debug.wrong_dir.push((o_entity, output.dir, input.dir));
                     }
@@ -136,6 +182,10 @@ fn output_item_stuff_hookup_system(
                 Option<&mut Merger>,
                 Option<&mut RandomItemGenerator>,
                 Option<&mut Belt>,
+                Option<&mut Splitter>,
+                Option<&mut Assembler>,
+                Option<&mut Chest>,
+                Option<&mut MiningDrill>,
             ),
         ),
         Changed<MultipleOutputs>,
@@ -143,19 +193,24 @@ fn output_item_stuff_hookup_system(
 ) {
     for ((_entity, outputs), it) in entities.iter_mut() {
         if let Some(mut merger) = it.0 {
-            let len = outputs.outputs.len();
-            merger.outputs.resize(len, Entity::new(0));
-            for i in 0..len {
-                if let Some(e) = outputs.outputs[i].entity {
-                    merger.outputs[i] = e;
-                }
-            }
+            merger.outputs = outputs.outputs.iter().filter_map(|it| it.entity).collect();
             // debug!("output  {:?} set to {:?}", entity, merger.outputs);
+        } else if let Some(mut splitter) = it.3 {
+            for (i, output) in outputs.outputs.iter().take(2).enumerate() {
+                splitter.outputs[i] = output.entity;
+            }
+        } else if let Some(mut assembler) = it.4 {
+            assembler.outputs = outputs.outputs.iter().map(|it| it.entity).collect();
+        } else if let Some(mut chest) = it.5 {
+            chest.output = outputs.outputs[0].entity;
+        } else if let Some(mut drill) = it.6 {
+            drill.output = outputs.outputs[0].entity;
         } else if let Some(mut item_gen) = it.1 {
             item_gen.output = outputs.outputs[0].entity;
             // debug!("output  {:?} set to {:?}", entity, item_gen.output);
         } else if let Some(mut belt) = it.2 {
             belt.output = outputs.outputs[0].entity;
+            belt.output_lane = outputs.outputs[0].lane;
             // debug!("output  {:?} set to {:?}", entity, belt.output);
         }
     }
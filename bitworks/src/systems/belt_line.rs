@@ -0,0 +1,583 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    Belt, BeltItem, CompassDir, Inserter, ItemInput, Lane, MapCache, MapLayer, MapPos,
+    MultipleOutputs, SingleInput,
+};
+
+/// Belts whose lines are split and fused again because something changed around them,
+/// and what everything puts items onto, so only the belts around a change are looked at.
+#[derive(Default)]
+pub struct BeltLines {
+    dirty: HashSet<Entity>,
+    /// what belts, buildings and inserters put items onto and the lane if they side load
+    outputs: HashMap<Entity, Vec<(Entity, Option<Lane>)>>,
+    /// everything which puts items onto an entity
+    feeders: HashMap<Entity, HashSet<Entity>>,
+    /// the members of every line by its first belt, they are known even after it is despawned
+    lines: HashMap<Entity, Vec<Entity>>,
+}
+
+impl BeltLines {
+    /// marks `entity` and what it puts onto, before and after, as changed
+    fn set_outputs(&mut self, entity: Entity, outputs: Vec<(Entity, Option<Lane>)>) {
+        self.dirty.insert(entity);
+        for (target, _) in self.outputs.remove(&entity).unwrap_or_default() {
+            self.dirty.insert(target);
+            if let Some(feeders) = self.feeders.get_mut(&target) {
+                feeders.remove(&entity);
+                if feeders.is_empty() {
+                    self.feeders.remove(&target);
+                }
+            }
+        }
+        for (target, _) in outputs.iter() {
+            self.dirty.insert(*target);
+            self.feeders.entry(*target).or_default().insert(entity);
+        }
+        if !outputs.is_empty() {
+            self.outputs.insert(entity, outputs);
+        }
+    }
+
+    /// the belt `belt` puts onto from behind, if it is its only output
+    fn front_output(&self, belt: Entity) -> Option<Entity> {
+        match self.outputs.get(&belt)?.as_slice() {
+            [(output, None)] => Some(*output),
+            _ => None,
+        }
+    }
+
+    /// the output of a belt itself, not the one of its line
+    fn own_output(&self, belt: Entity) -> (Option<Entity>, Option<Lane>) {
+        match self.outputs.get(&belt).and_then(|it| it.first()) {
+            Some((output, lane)) => (Some(*output), *lane),
+            None => (None, None),
+        }
+    }
+
+    fn single_feeder(&self, entity: Entity) -> Option<Entity> {
+        let feeders = self.feeders.get(&entity)?;
+        if feeders.len() == 1 {
+            feeders.iter().next().copied()
+        } else {
+            None
+        }
+    }
+
+    fn is_side_loaded(&self, entity: Entity) -> bool {
+        self.feeders.get(&entity).map_or(false, |feeders| {
+            feeders.iter().any(|feeder| {
+                self.outputs[feeder]
+                    .iter()
+                    .any(|(target, lane)| *target == entity && lane.is_some())
+            })
+        })
+    }
+}
+
+/// On the first belt of a transport line.
+/// Runs of belts which only feed each other are fused into one line,
+/// the first belt then simulates the items of the whole run with all its segments.
+pub struct BeltLine {
+    /// the number of segments the first belt has itself
+    own_segments: usize,
+    /// the other belts of the line and where they start on the line
+    members: Vec<(Entity, f32)>,
+}
+
+impl BeltLine {
+    pub fn members(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.members.iter().map(|(entity, _)| *entity)
+    }
+
+    /// the belt of the line that an item at `pos` on the line is on
+    pub fn belt_at(&self, head: Entity, pos: f32) -> Entity {
+        self.locate(head, pos).0
+    }
+
+    /// the belt of the line that an item at `pos` on the line is on and its pos on that belt
+    pub fn locate(&self, head: Entity, pos: f32) -> (Entity, f32) {
+        self.members
+            .iter()
+            .rev()
+            .find(|(_, offset)| *offset <= pos)
+            .map_or((head, pos), |(entity, offset)| (*entity, pos - offset))
+    }
+
+    /// Takes the items of the line off `belt`, its first belt, and cuts it back to its own segments
+    /// and `output`. Returns the items by the belt they are on, with their pos on that belt.
+    fn take_items(
+        &self,
+        head: Entity,
+        belt: &mut Belt,
+        output: (Option<Entity>, Option<Lane>),
+    ) -> Vec<(Entity, Vec<BeltItem>)> {
+        let items = belt.take_items();
+        belt.segments.truncate(self.own_segments);
+        belt.output = output.0;
+        belt.output_lane = output.1;
+
+        let mut owners: Vec<(Entity, Vec<BeltItem>)> = std::iter::once(head)
+            .chain(self.members())
+            .map(|entity| (entity, Vec::new()))
+            .collect();
+        for mut item in items {
+            let (owner, pos) = self.locate(head, item.pos);
+            item.pos = pos;
+            if let Some((_, items)) = owners.iter_mut().find(|(entity, _)| *entity == owner) {
+                items.push(item);
+            }
+        }
+        owners
+    }
+}
+
+/// On the other belts of a transport line, they are skipped by the simulation
+/// and carry no items themselves while fused.
+pub struct FusedInto(pub Entity);
+
+/// Adds belts spawned without the map to `BeltLines` and takes despawned belts,
+/// buildings and inserters out, their removal can only be seen in the frame it happens.
+pub fn belt_line_dirty_system(
+    mut belt_lines: ResMut<BeltLines>,
+    unmapped_belts: Query<(Entity, &Belt), (Added<Belt>, Without<MultipleOutputs>)>,
+    removed_outputs: RemovedComponents<MultipleOutputs>,
+    removed_inserters: RemovedComponents<Inserter>,
+    removed_belts: RemovedComponents<Belt>,
+) {
+    // belts spawned with their output set instead of being hooked up on the map
+    for (entity, belt) in unmapped_belts.iter() {
+        let output = belt.output.map(|it| (it, belt.output_lane));
+        belt_lines.set_outputs(entity, output.into_iter().collect());
+    }
+    for entity in removed_outputs.iter().chain(removed_inserters.iter()) {
+        belt_lines.set_outputs(entity, Vec::new());
+    }
+    belt_lines.dirty.extend(removed_belts.iter());
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn belt_line_system(
+    mut cmds: Commands,
+    mut belt_lines: ResMut<BeltLines>,
+    mut belts: Query<&mut Belt>,
+    mut item_inputs: Query<&mut ItemInput>,
+    lines: Query<&BeltLine>,
+    fused: Query<&FusedInto>,
+    changed_outputs: Query<(Entity, &MultipleOutputs), Changed<MultipleOutputs>>,
+    new_inputs: Query<(Entity, Option<&MapPos>), Added<SingleInput>>,
+    new_inserters: Query<(Entity, &MapPos, &Inserter), Added<Inserter>>,
+    inserters: Query<&Inserter>,
+    map: Option<Res<MapCache>>,
+) {
+    let belt_lines = &mut *belt_lines;
+    let map = map.as_deref();
+
+    for (entity, outputs) in changed_outputs.iter() {
+        let outputs = outputs
+            .outputs
+            .iter()
+            .filter_map(|it| Some((it.entity?, it.lane)))
+            .collect();
+        belt_lines.set_outputs(entity, outputs);
+    }
+    // inserters drop onto belts without outputs to hook up
+    for (entity, pos, inserter) in new_inserters.iter() {
+        let target = map.and_then(|map| map.at(MapLayer::Belts, &pos.step(inserter.drop)));
+        belt_lines.set_outputs(entity, target.map(|it| (it, None)).into_iter().collect());
+    }
+    for (entity, pos) in new_inputs.iter() {
+        belt_lines.dirty.insert(entity);
+        for dir in CompassDir::ALL.iter().copied() {
+            let neighbour = map
+                .zip(pos)
+                .and_then(|(map, pos)| map.at(MapLayer::Buildings, &pos.step(dir)));
+            if let Some((neighbour, inserter)) =
+                neighbour.and_then(|it| Some((it, inserters.get(it).ok()?)))
+            {
+                if inserter.drop == dir.opposite() {
+                    belt_lines.set_outputs(neighbour, vec![(entity, None)]);
+                }
+            }
+        }
+    }
+
+    if belt_lines.dirty.is_empty() {
+        return;
+    }
+    let dirty = std::mem::take(&mut belt_lines.dirty);
+
+    // belts which are on no line until they are fused again
+    let mut free = HashSet::default();
+    for belt in dirty {
+        let head = fused.get(belt).map_or(belt, |it| it.0);
+        if !free.contains(&head) {
+            split_line(&mut cmds, head, belt_lines, &lines, &mut belts, &mut free);
+        }
+    }
+
+    let mut done = HashSet::default();
+    let starts: Vec<Entity> = free.iter().copied().collect();
+    for start in starts {
+        if done.contains(&start) {
+            continue;
+        }
+
+        // back to the first belt of the run, a run around in a circle is never fused
+        let mut head = start;
+        let mut seen = HashSet::default();
+        let mut circle = false;
+        while let Some(previous) = belt_lines.single_feeder(head) {
+            if !fuses(belt_lines, &belts, previous, head) {
+                break;
+            }
+            if !seen.insert(previous) {
+                circle = true;
+                break;
+            }
+            head = previous;
+        }
+        if circle {
+            continue;
+        }
+        // the run may continue into a line which did not change itself
+        for belt in seen.iter().copied().chain(std::iter::once(head)) {
+            let line_head = fused.get(belt).map_or(belt, |it| it.0);
+            if !free.contains(&line_head) {
+                split_line(
+                    &mut cmds, line_head, belt_lines, &lines, &mut belts, &mut free,
+                );
+            }
+        }
+
+        let mut members = Vec::new();
+        let mut current = head;
+        while let Some(next) = belt_lines.front_output(current) {
+            if next == head || members.contains(&next) || !fuses(belt_lines, &belts, current, next)
+            {
+                break;
+            }
+            let line_head = fused.get(next).map_or(next, |it| it.0);
+            if !free.contains(&line_head) {
+                split_line(
+                    &mut cmds, line_head, belt_lines, &lines, &mut belts, &mut free,
+                );
+            }
+            members.push(next);
+            current = next;
+        }
+
+        done.insert(head);
+        done.extend(members.iter().copied());
+        if !members.is_empty() {
+            fuse_line(&mut cmds, head, &members, &mut belts, &mut item_inputs);
+            belt_lines.lines.insert(head, members);
+        }
+    }
+}
+
+/// A belt fuses with the belt it puts onto if that gets items from nothing else,
+/// both are of the same tier and neither of them is side loaded.
+fn fuses(belt_lines: &BeltLines, belts: &Query<&mut Belt>, feeder: Entity, output: Entity) -> bool {
+    let tier = |belt| belts.get_component::<Belt>(belt).ok().map(|it| it.tier);
+    let same_speed = match (tier(feeder), tier(output)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    };
+
+    same_speed
+        && feeder != output
+        && belt_lines.front_output(feeder) == Some(output)
+        && belt_lines.single_feeder(output) == Some(feeder)
+        && !belt_lines.is_side_loaded(feeder)
+}
+
+fn fuse_line(
+    cmds: &mut Commands,
+    head: Entity,
+    members: &[Entity],
+    belts: &mut Query<&mut Belt>,
+    item_inputs: &mut Query<&mut ItemInput>,
+) {
+    let (own_segments, mut offset) = match belts.get_mut(head) {
+        Ok(belt) => (belt.segments.len(), belt.total_length()),
+        Err(_) => return,
+    };
+
+    let mut segments = Vec::new();
+    let mut items = Vec::new();
+    let mut line_members = Vec::new();
+    let mut output = (None, None);
+
+    for member in members.iter().copied() {
+        if let Ok(mut belt) = belts.get_mut(member) {
+            items.extend(belt.take_items().into_iter().map(|mut item| {
+                item.pos += offset;
+                item
+            }));
+
+            // items on their way onto this belt move onto the line
+            if let Ok(mut item_input) = item_inputs.get_mut(member) {
+                while let Some(mut item) = item_input.pop_oldest_item() {
+                    item.pos += offset;
+                    items.push(item);
+                }
+            }
+
+            segments.extend(belt.segments.iter().copied());
+            line_members.push((member, offset));
+            offset += belt.total_length();
+            output = (belt.output, belt.output_lane);
+
+            cmds.entity(member).insert(FusedInto(head));
+        }
+    }
+
+    if let Ok(mut belt) = belts.get_mut(head) {
+        belt.segments.extend(segments);
+        belt.output = output.0;
+        belt.output_lane = output.1;
+
+        for item in items {
+            belt.add_item(item);
+        }
+    }
+
+    cmds.entity(head).insert(BeltLine {
+        own_segments,
+        members: line_members,
+    });
+}
+
+/// Gives the items of the line of `head` back to the belts they are on,
+/// all of them are `free` to be fused again afterwards.
+fn split_line(
+    cmds: &mut Commands,
+    head: Entity,
+    belt_lines: &mut BeltLines,
+    lines: &Query<&BeltLine>,
+    belts: &mut Query<&mut Belt>,
+    free: &mut HashSet<Entity>,
+) {
+    free.insert(head);
+    let members = match belt_lines.lines.remove(&head) {
+        Some(members) => members,
+        None => return,
+    };
+
+    if let Ok(line) = lines.get(head) {
+        let owners = match belts.get_mut(head) {
+            Ok(mut belt) => line.take_items(head, &mut belt, belt_lines.own_output(head)),
+            Err(_) => Vec::new(),
+        };
+        for (entity, items) in owners {
+            if let Ok(mut belt) = belts.get_mut(entity) {
+                for item in items {
+                    belt.add_item(item);
+                }
+            }
+        }
+        cmds.entity(head).remove::<BeltLine>();
+    }
+
+    for member in members {
+        cmds.entity(member).remove::<FusedInto>();
+        free.insert(member);
+    }
+}
+
+/// Splits the line `belt` is on right away, every belt gets back the items on it.
+/// Call this before despawning a belt, a line keeps all its items on its first belt
+/// and they would be gone with it.
+pub fn unfuse_belt(world: &mut World, belt: Entity) {
+    let head = world.get::<FusedInto>(belt).map_or(belt, |it| it.0);
+    let line = match world
+        .get_entity_mut(head)
+        .and_then(|mut it| it.remove::<BeltLine>())
+    {
+        Some(line) => line,
+        None => return,
+    };
+
+    let output = world
+        .get_resource::<BeltLines>()
+        .map_or((None, None), |it| it.own_output(head));
+    let owners = match world.get_mut::<Belt>(head) {
+        Some(mut belt) => line.take_items(head, &mut belt, output),
+        None => Vec::new(),
+    };
+    for (entity, items) in owners {
+        if let Some(mut belt) = world.get_mut::<Belt>(entity) {
+            for item in items {
+                belt.add_item(item);
+            }
+        }
+    }
+
+    for member in line.members() {
+        if let Some(mut member) = world.get_entity_mut(member) {
+            member.remove::<FusedInto>();
+        }
+    }
+    if let Some(mut belt_lines) = world.get_resource_mut::<BeltLines>() {
+        belt_lines.lines.remove(&head);
+        belt_lines.dirty.insert(head);
+        belt_lines.dirty.extend(line.members());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    /// straight belts and a bend from a generator into a sink
+    fn belt_run() -> Vec<Simple> {
+        use CompassDir::*;
+        let belt =
+            |x: i32, y: i32, d_in, out| Simple::Belt(map_pos(x, y), d_in, out, BeltTier::Basic);
+        let mut simples = vec![Simple::ItemGenerator(map_pos(0, 0), E)];
+        simples.extend((1..7).map(|x| belt(x, 0, W, E)));
+        simples.extend(vec![
+            belt(7, 0, W, N),
+            belt(7, 1, S, N),
+            belt(7, 2, S, E),
+            belt(8, 2, W, E),
+            Simple::NullSink(map_pos(9, 2), W),
+        ]);
+        simples
+    }
+
+    /// `BeltPlugin` without fusing belts into lines
+    struct UnfusedBeltPlugin;
+    impl Plugin for UnfusedBeltPlugin {
+        fn build(&self, app: &mut AppBuilder) {
+            app.insert_resource(ItemRegistry::load_default())
+                .insert_resource(AwakeChunks::default())
+                .add_system_set_to_stage(
+                    SimulationStage,
+                    SystemSet::new()
+                        .with_system(chunk_activity_system.system().label("chunks"))
+                        .with_system(
+                            belt_input_system
+                                .system()
+                                .label("belt_input")
+                                .after("chunks"),
+                        )
+                        .with_system(
+                            belt_advance_items_system
+                                .system()
+                                .label("belt_advance")
+                                .after("belt_input"),
+                        )
+                        .with_system(
+                            null_sink_system
+                                .system()
+                                .label("sink")
+                                .after("belt_advance"),
+                        )
+                        .with_system(random_item_generator_system.system().after("sink")),
+                );
+        }
+    }
+
+    fn belt_run_app(fused: bool) -> App {
+        let mut app = App::build();
+        app.insert_resource(SimulationTime::stepped(1))
+            .insert_resource(SimRng::new(42))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(SimulationPlugin)
+            .add_plugin(MapPlugin)
+            .add_plugin(SimpleSpawnerPlugin)
+            .add_plugin(BeltInputOutputHookupPlugin);
+        if fused {
+            app.add_plugin(BeltPlugin);
+        } else {
+            app.add_plugin(UnfusedBeltPlugin);
+        }
+        let mut app = app.app;
+        for simple in belt_run() {
+            app.world.spawn().insert(simple);
+        }
+        for _ in 0..1200 {
+            app.update();
+        }
+        app
+    }
+
+    /// the items of every belt by its building, as a saved world has them
+    fn belt_items(world: &mut World) -> Vec<(String, Vec<BeltItem>)> {
+        let mut buildings: Vec<(String, Vec<BeltItem>)> = WorldSave::capture(world)
+            .buildings
+            .into_iter()
+            .map(|it| (format!("{:?}", it.simple), it.belt_items))
+            .collect();
+        buildings.sort_by(|a, b| a.0.cmp(&b.0));
+        buildings
+    }
+
+    fn assert_same_items(expected: &[(String, Vec<BeltItem>)], actual: &[(String, Vec<BeltItem>)]) {
+        assert_eq!(expected.len(), actual.len());
+        for ((building, expected), (_, actual)) in expected.iter().zip(actual) {
+            assert_eq!(expected.len(), actual.len(), "{}", building);
+            for (expected, actual) in expected.iter().zip(actual) {
+                assert_eq!((expected.item, expected.lane), (actual.item, actual.lane));
+                assert!(
+                    (expected.pos - actual.pos).abs() < 0.01,
+                    "{} {:?} {:?}",
+                    building,
+                    expected,
+                    actual
+                );
+            }
+        }
+    }
+
+    fn consumed(world: &mut World) -> u64 {
+        world
+            .query::<&NullSink>()
+            .iter(world)
+            .map(|it| it.consumed())
+            .sum()
+    }
+
+    #[test]
+    fn fused_and_unfused_belts_move_items_alike() {
+        let mut fused = belt_run_app(true);
+        let mut unfused = belt_run_app(false);
+        let fused = &mut fused.world;
+        let unfused = &mut unfused.world;
+
+        assert!(fused.query::<&BeltLine>().iter(fused).count() > 0);
+        assert_eq!(0, unfused.query::<&BeltLine>().iter(unfused).count());
+        assert!(consumed(fused) > 0);
+        assert_eq!(consumed(fused), consumed(unfused));
+        assert_same_items(&belt_items(fused), &belt_items(unfused));
+    }
+
+    #[test]
+    fn unfusing_keeps_the_items_of_the_line() {
+        let mut app = belt_run_app(true);
+        let world = &mut app.world;
+        let before = belt_items(world);
+        assert!(before.iter().any(|(_, items)| items.len() > 1));
+
+        let (head, member) = world
+            .query::<(Entity, &BeltLine)>()
+            .iter(world)
+            .find_map(|(head, line)| Some((head, line.members().nth(2)?)))
+            .expect("a line of four belts");
+        unfuse_belt(world, member);
+
+        assert!(world.get::<BeltLine>(head).is_none());
+        assert!(world
+            .query::<&FusedInto>()
+            .iter(world)
+            .all(|it| it.0 != head));
+        assert_same_items(&before, &belt_items(world));
+    }
+}
@@ -0,0 +1,139 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::utils::HashMap;
+
+use crate::{belt_chain, CompassDir, MapCache, MapPos, Simple, SingleInput, SingleOutput};
+
+/// cost of one belt
+const STEP_COST: u32 = 10;
+/// cost of a corner, a route is only one belt longer to save four corners
+const TURN_COST: u32 = 3;
+/// cells around the ports the search may go, so an unreachable port does not search forever
+pub const ROUTE_MARGIN: i32 = 16;
+
+/// A cell a route starts or ends on and the direction items travel there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Port {
+    pub cell: MapPos,
+    pub dir: CompassDir,
+}
+
+impl Port {
+    /// the cell `output` of a building at `pos` puts items onto
+    pub fn from_output(pos: &MapPos, output: &SingleOutput) -> Self {
+        Self {
+            cell: (*pos + output.pos).step(output.dir),
+            dir: output.dir,
+        }
+    }
+
+    /// the cell `input` of a building at `pos` takes items from
+    pub fn from_input(pos: &MapPos, input: &SingleInput) -> Self {
+        Self {
+            cell: (*pos + input.pos).step(input.dir),
+            dir: input.dir.opposite(),
+        }
+    }
+}
+
+/// Belts from `from` to `to` over free cells of the `MapCache`,
+/// the shortest route with the fewest corners.
+pub fn route_belt(map: &MapCache, from: Port, to: Port) -> Option<Vec<Simple>> {
+    route_belt_with(from, to, |pos| map.is_free(pos))
+}
+
+/// like `route_belt` but `is_free` decides where belts can go,
+/// so routes which are not spawned yet can be kept clear of
+pub fn route_belt_with(
+    from: Port,
+    to: Port,
+    is_free: impl Fn(&MapPos) -> bool,
+) -> Option<Vec<Simple>> {
+    let path = find_path(from, to, is_free)?;
+    Some(belt_chain(&path, Some(from.dir.opposite()), Some(to.dir)))
+}
+
+/// A* over cells and the direction items arrive in them
+fn find_path(from: Port, to: Port, is_free: impl Fn(&MapPos) -> bool) -> Option<Vec<MapPos>> {
+    if !is_free(&from.cell) || !is_free(&to.cell) {
+        return None;
+    }
+
+    let min = MapPos::new(
+        from.cell.x.min(to.cell.x) - ROUTE_MARGIN,
+        from.cell.y.min(to.cell.y) - ROUTE_MARGIN,
+    );
+    let max = MapPos::new(
+        from.cell.x.max(to.cell.x) + ROUTE_MARGIN,
+        from.cell.y.max(to.cell.y) + ROUTE_MARGIN,
+    );
+    let inside =
+        |pos: &MapPos| pos.x >= min.x && pos.x <= max.x && pos.y >= min.y && pos.y <= max.y;
+    let estimate =
+        |pos: &MapPos| STEP_COST * ((pos.x - to.cell.x).abs() + (pos.y - to.cell.y).abs()) as u32;
+    let turn = |a: CompassDir, b: CompassDir| if a == b { 0 } else { TURN_COST };
+
+    type State = (MapPos, CompassDir);
+    let start: State = (from.cell, from.dir);
+    let mut costs: HashMap<State, u32> = HashMap::default();
+    let mut came_from: HashMap<State, State> = HashMap::default();
+    // states by index, the heap orders them by estimated total cost
+    let mut states: Vec<(State, u32, bool)> = vec![(start, 0, false)];
+    let mut open = BinaryHeap::new();
+    costs.insert(start, 0);
+    open.push(Reverse((estimate(&from.cell), 0)));
+
+    while let Some(Reverse((_, index))) = open.pop() {
+        let (state, cost, done) = states[index];
+        if done {
+            return Some(walk_back(&came_from, state));
+        }
+        if costs.get(&state).map_or(false, |best| *best < cost) {
+            continue;
+        }
+        let (pos, dir) = state;
+
+        // the last belt turns onto the input
+        if pos == to.cell {
+            let total = cost + turn(dir, to.dir);
+            states.push((state, total, true));
+            open.push(Reverse((total, states.len() - 1)));
+            continue;
+        }
+
+        for next_dir in CompassDir::ALL.iter().copied() {
+            if next_dir == dir.opposite() {
+                continue;
+            }
+            let next_pos = pos.step(next_dir);
+            if !inside(&next_pos) || !is_free(&next_pos) {
+                continue;
+            }
+
+            let next: State = (next_pos, next_dir);
+            let next_cost = cost + STEP_COST + turn(dir, next_dir);
+            if costs.get(&next).map_or(false, |best| *best <= next_cost) {
+                continue;
+            }
+            costs.insert(next, next_cost);
+            came_from.insert(next, state);
+            states.push((next, next_cost, false));
+            open.push(Reverse((next_cost + estimate(&next_pos), states.len() - 1)));
+        }
+    }
+
+    None
+}
+
+fn walk_back(
+    came_from: &HashMap<(MapPos, CompassDir), (MapPos, CompassDir)>,
+    mut state: (MapPos, CompassDir),
+) -> Vec<MapPos> {
+    let mut path = vec![state.0];
+    while let Some(previous) = came_from.get(&state) {
+        path.push(previous.0);
+        state = *previous;
+    }
+    path.reverse();
+    path
+}
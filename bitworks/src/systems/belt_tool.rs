@@ -0,0 +1,241 @@
+use bevy::prelude::*;
+use bevy_egui::EguiContext;
+use bevy_mod_picking::PickingCamera;
+
+use crate::{
+    route_belt, BeltTier, CompassDir, DebugLines, MapCache, MapEdit, MapLayer, MapPos, Placed,
+    Port, Simple, UndoStack, TILE_SIZE,
+};
+
+pub struct BeltToolPlugin;
+impl Plugin for BeltToolPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<BeltDrag>()
+            .add_system(belt_drag_system.system())
+            .add_system(belt_drag_preview_system.system());
+    }
+}
+
+/// The cells the mouse was dragged over with the left button held,
+/// released they are built as a chain of belts, one step of the `UndoStack<MapEdit>`.
+/// With shift held the belts are routed from the first to the last cell around everything in the way.
+#[derive(Default)]
+pub struct BeltDrag {
+    pub path: Vec<MapPos>,
+    pub dragging: bool,
+}
+
+impl BeltDrag {
+    /// walks to `cell` one neighbour at a time, dragging back onto the path cuts it there
+    pub fn extend(&mut self, cell: MapPos) {
+        let mut last = match self.path.last() {
+            Some(last) => *last,
+            None => {
+                self.path.push(cell);
+                return;
+            }
+        };
+
+        while last != cell {
+            let dir = if last.x < cell.x {
+                CompassDir::E
+            } else if last.x > cell.x {
+                CompassDir::W
+            } else if last.y < cell.y {
+                CompassDir::N
+            } else {
+                CompassDir::S
+            };
+            last = last.step(dir);
+
+            match self.path.iter().position(|it| *it == last) {
+                Some(index) => self.path.truncate(index + 1),
+                None => self.path.push(last),
+            }
+        }
+    }
+}
+
+/// Belts along `path`, each one going to the next cell.
+/// The first belt is fed from `first_in` and the last one puts onto `last_out`,
+/// otherwise they continue straight.
+pub fn belt_chain(
+    path: &[MapPos],
+    first_in: Option<CompassDir>,
+    last_out: Option<CompassDir>,
+) -> Vec<Simple> {
+    path.iter()
+        .enumerate()
+        .map(|(index, pos)| {
+            // direction of travel onto this cell
+            let travel = index
+                .checked_sub(1)
+                .and_then(|previous| path[previous].dir_to(pos));
+            let out_dir = path
+                .get(index + 1)
+                .and_then(|next| pos.dir_to(next))
+                .or(last_out)
+                .or(travel)
+                .unwrap_or(CompassDir::E);
+            let in_dir = travel
+                .map(|it| it.opposite())
+                .or(first_in)
+                .filter(|it| *it != out_dir)
+                .unwrap_or_else(|| out_dir.opposite());
+
+            Simple::Belt(*pos, in_dir, out_dir, BeltTier::Basic)
+        })
+        .collect()
+}
+
+/// like `belt_chain`, but the ends join belts next to them which end there
+pub fn joined_belt_chain(path: &[MapPos], map: &MapCache, placed: &Query<&Placed>) -> Vec<Simple> {
+    let (first, last) = match (path.first(), path.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Vec::new(),
+    };
+    let second = path.get(1).copied();
+    let before_last = path.len().checked_sub(2).map(|index| path[index]);
+
+    let neighbour_belt = |pos: MapPos, dir: CompassDir, except: Option<MapPos>| {
+        let neighbour = pos.step(dir);
+        if Some(neighbour) == except {
+            return None;
+        }
+        let entity = map.at(MapLayer::Belts, &neighbour)?;
+        belt_dirs(&placed.get(entity).ok()?.0)
+    };
+
+    // a belt next to the first cell which puts onto it
+    let first_in = CompassDir::ALL.iter().copied().find(|dir| {
+        neighbour_belt(first, *dir, second).map_or(false, |(_, out)| out == dir.opposite())
+    });
+    // a belt next to the last cell which takes from it
+    let last_out = CompassDir::ALL.iter().copied().find(|dir| {
+        neighbour_belt(last, *dir, before_last)
+            .map_or(false, |(in_dir, _)| in_dir == dir.opposite())
+    });
+
+    belt_chain(path, first_in, last_out)
+}
+
+/// like `joined_belt_chain` but only the first and the last cell are kept,
+/// the belts between them are routed with `route_belt`
+pub fn routed_belt_chain(
+    path: &[MapPos],
+    map: &MapCache,
+    placed: &Query<&Placed>,
+) -> Option<Vec<Simple>> {
+    let chain = joined_belt_chain(path, map, placed);
+    let (first_in, _) = belt_dirs(chain.first()?)?;
+    let (_, last_out) = belt_dirs(chain.last()?)?;
+
+    let from = Port {
+        cell: *path.first()?,
+        dir: first_in.opposite(),
+    };
+    let to = Port {
+        cell: *path.last()?,
+        dir: last_out,
+    };
+    route_belt(map, from, to)
+}
+
+/// in and out direction of a belt
+fn belt_dirs(simple: &Simple) -> Option<(CompassDir, CompassDir)> {
+    match simple {
+        Simple::Belt(_, in_dir, out_dir, _) => Some((*in_dir, *out_dir)),
+        _ => None,
+    }
+}
+
+/// the cell under the mouse, found on anything pickable
+pub fn cursor_cell(cameras: &Query<&PickingCamera>) -> Option<MapPos> {
+    let (_, intersection) = cameras.iter().find_map(|camera| camera.intersect_top())?;
+    let pos = intersection.position() / TILE_SIZE;
+    Some(MapPos::new(pos.x.round() as i32, pos.y.round() as i32))
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn belt_drag_system(
+    mut drag: ResMut<BeltDrag>,
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    cameras: Query<&PickingCamera>,
+    egui: Option<Res<EguiContext>>,
+    map: Res<MapCache>,
+    placed: Query<&Placed>,
+    mut undo: ResMut<UndoStack<MapEdit>>,
+) {
+    let over_ui = egui.map_or(false, |egui| egui.ctx().wants_pointer_input());
+
+    if buttons.just_pressed(MouseButton::Left) && !over_ui {
+        drag.path.clear();
+        drag.dragging = true;
+    }
+    if !drag.dragging {
+        return;
+    }
+
+    if let Some(cell) = cursor_cell(&cameras) {
+        drag.extend(cell);
+    }
+
+    if buttons.just_released(MouseButton::Left) {
+        drag.dragging = false;
+        let path = std::mem::take(&mut drag.path);
+        let routed = if keys.pressed(KeyCode::LShift) || keys.pressed(KeyCode::RShift) {
+            routed_belt_chain(&path, &map, &placed)
+        } else {
+            None
+        };
+        let simples = routed.unwrap_or_else(|| joined_belt_chain(&path, &map, &placed));
+
+        // belts on taken cells are left out, undoing them would remove what is there
+        undo.begin_group();
+        for simple in simples {
+            let free = simple.footprint().map_or(false, |(pos, footprint)| {
+                footprint.cells_at(pos).all(|cell| map.is_free(&cell))
+            });
+            if free {
+                undo.push(MapEdit::Place(simple.into(), None));
+            }
+        }
+        undo.end_group();
+    }
+}
+
+/// the path while dragging, red on cells which are taken already
+pub fn belt_drag_preview_system(
+    drag: Res<BeltDrag>,
+    map: Res<MapCache>,
+    mut lines: ResMut<DebugLines>,
+) {
+    if !drag.dragging {
+        return;
+    }
+
+    let center = |pos: &MapPos| (TILE_SIZE * pos.vec2()).extend(1.0);
+    for (index, pos) in drag.path.iter().enumerate() {
+        let color = if map.is_free(pos) {
+            Color::WHITE
+        } else {
+            Color::RED
+        };
+        let next = drag.path.get(index + 1).unwrap_or(pos);
+        let half = 0.25 * TILE_SIZE;
+        lines.line_colored(
+            center(pos) - Vec3::X * half,
+            center(pos) + Vec3::X * half,
+            0.0,
+            color,
+        );
+        lines.line_colored(
+            center(pos) - Vec3::Y * half,
+            center(pos) + Vec3::Y * half,
+            0.0,
+            color,
+        );
+        lines.line_colored(center(pos), center(next), 0.0, color);
+    }
+}
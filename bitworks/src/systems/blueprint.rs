@@ -0,0 +1,212 @@
+use bevy::{prelude::*, utils::HashSet};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map_pos, rotate, CompassDir, Lane, MapCache, MapLayer, MapPos, Placed, PowerConsumer, Simple,
+    SplitterSettings,
+};
+
+/// `Simple` entries with their positions relative to an origin,
+/// captured from the map and pasted anywhere else turned or mirrored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Blueprint {
+    pub entries: Vec<Simple>,
+}
+
+impl Blueprint {
+    /// every building with all its cells within `min` and `max`, both inclusive,
+    /// relative to `min`
+    pub fn capture(
+        map: &MapCache,
+        buildings: &Query<(&Placed, Option<&PowerConsumer>)>,
+        min: MapPos,
+        max: MapPos,
+    ) -> Self {
+        let inside =
+            |pos: MapPos| pos.x >= min.x && pos.x <= max.x && pos.y >= min.y && pos.y <= max.y;
+        let mut seen = HashSet::default();
+        let mut entries = Vec::new();
+
+        for layer in [MapLayer::Buildings, MapLayer::Belts, MapLayer::Pipes].iter() {
+            for (_, entity) in map.entities_in_rect(*layer, min, max) {
+                if !seen.insert(entity) {
+                    continue;
+                }
+                let (placed, power) = match buildings.get(entity) {
+                    Ok(building) => building,
+                    Err(_) => continue,
+                };
+                let fits = placed.0.footprint().map_or(false, |(pos, footprint)| {
+                    footprint.cells_at(pos).all(inside)
+                });
+                if fits {
+                    entries.push(placed.simple(power).translated(map_pos(0, 0) - min));
+                }
+            }
+        }
+
+        Self { entries }
+    }
+
+    /// turned around the origin like `N` would be turned to `by`
+    pub fn rotated(&self, by: CompassDir) -> Self {
+        Self {
+            entries: self.entries.iter().map(|it| it.rotated(by)).collect(),
+        }
+    }
+
+    /// east and west swapped around the origin
+    pub fn mirrored(&self) -> Self {
+        Self {
+            entries: self.entries.iter().map(Simple::mirrored).collect(),
+        }
+    }
+
+    /// the entries with their origin at `at`
+    pub fn placed_at(&self, at: MapPos) -> impl Iterator<Item = Simple> + '_ {
+        self.entries.iter().map(move |it| it.translated(at))
+    }
+
+    /// spawns the entries for `simple_spawner_system`, occupied cells are left alone
+    pub fn paste(&self, cmds: &mut Commands, at: MapPos) {
+        for simple in self.placed_at(at) {
+            cmds.spawn().insert(simple);
+        }
+    }
+
+    pub fn to_ron(&self) -> Result<String, ron::Error> {
+        ron::ser::to_string_pretty(self, PrettyConfig::new())
+    }
+
+    /// RON on a single line, to copy around as text
+    pub fn to_compact_string(&self) -> Result<String, ron::Error> {
+        ron::ser::to_string(self)
+    }
+}
+
+/// reads both, `to_ron` and `to_compact_string`
+impl std::str::FromStr for Blueprint {
+    type Err = ron::Error;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        ron::de::from_str(text)
+    }
+}
+
+impl Simple {
+    pub fn translated(&self, offset: MapPos) -> Simple {
+        self.map(&|pos| pos + offset, &|dir| dir)
+    }
+
+    /// turned around `(0, 0)` like `N` would be turned to `by`
+    pub fn rotated(&self, by: CompassDir) -> Simple {
+        self.map(&|pos| rotate(pos, by), &|dir| dir.rotated(by))
+    }
+
+    /// east and west swapped around `(0, 0)`
+    pub fn mirrored(&self) -> Simple {
+        self.map(&|pos| map_pos(-pos.x, pos.y), &|dir| dir.mirrored())
+            .with_mirrored_anchor()
+    }
+
+    /// buildings two cells wide are placed from their left cell, which was their right one
+    fn with_mirrored_anchor(self) -> Simple {
+        use Simple::*;
+        match self {
+            Merger2x2(pos, dir) => Merger2x2(pos.step(dir.left()), dir),
+            Splitter2x1(pos, dir, settings) => {
+                Splitter2x1(pos.step(dir.left()), dir, mirror_settings(settings))
+            }
+            Assembler(pos, dir, recipe) => Assembler(pos.step(dir.left()), dir, recipe),
+            MiningDrill(pos, dir) => MiningDrill(pos.step(dir.left()), dir),
+            Powered(watts, simple) => Powered(watts, Box::new(simple.with_mirrored_anchor())),
+            other => other,
+        }
+    }
+
+    /// every pos and every direction mapped
+    fn map(
+        &self,
+        pos: &dyn Fn(MapPos) -> MapPos,
+        dir: &dyn Fn(CompassDir) -> CompassDir,
+    ) -> Simple {
+        use Simple::*;
+        match self {
+            ItemGenerator(p, out) => ItemGenerator(pos(*p), dir(*out)),
+            Belt(p, d_in, out, tier) => Belt(pos(*p), dir(*d_in), dir(*out), *tier),
+            NullSink(p, d_in) => NullSink(pos(*p), dir(*d_in)),
+            Merger2x2(p, out) => Merger2x2(pos(*p), dir(*out)),
+            Splitter2x1(p, out, settings) => Splitter2x1(pos(*p), dir(*out), settings.clone()),
+            UndergroundEntrance(p, d) => UndergroundEntrance(pos(*p), dir(*d)),
+            UndergroundExit(p, d) => UndergroundExit(pos(*p), dir(*d)),
+            Assembler(p, out, recipe) => Assembler(pos(*p), dir(*out), recipe.clone()),
+            Chest(p, d_in, out) => Chest(pos(*p), dir(*d_in), out.map(dir)),
+            Inserter(p, pickup, drop, filter) => {
+                Inserter(pos(*p), dir(*pickup), dir(*drop), filter.clone())
+            }
+            MiningDrill(p, out) => MiningDrill(pos(*p), dir(*out)),
+            PowerPlant(p, watts) => PowerPlant(pos(*p), *watts),
+            PowerPole(p) => PowerPole(pos(*p)),
+            Powered(watts, simple) => Powered(*watts, Box::new(simple.map(pos, dir))),
+            Pipe(p) => Pipe(pos(*p)),
+            Tank(p) => Tank(pos(*p)),
+            Pump(p, out, fluid) => Pump(pos(*p), dir(*out), fluid.clone()),
+        }
+    }
+}
+
+/// left and right priorities swap sides
+fn mirror_settings(settings: SplitterSettings) -> SplitterSettings {
+    let mirror = |lane: Option<Lane>| {
+        lane.map(|lane| match lane {
+            Lane::Left => Lane::Right,
+            Lane::Right => Lane::Left,
+        })
+    };
+    SplitterSettings {
+        input_priority: mirror(settings.input_priority),
+        output_priority: mirror(settings.output_priority),
+        ..settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MapPlugin, MultipleOutputs, SimpleSpawnerPlugin};
+
+    #[test]
+    fn rotated_and_pasted_merger_outputs_from_both_cells() {
+        use CompassDir::*;
+        let blueprint = Blueprint {
+            entries: vec![Simple::Merger2x2(map_pos(0, 0), N)],
+        };
+
+        for by in [N, E, S, W].iter() {
+            let mut app = App::build();
+            app.add_plugin(MapPlugin).add_plugin(SimpleSpawnerPlugin);
+            let mut app = app.app;
+            let mut queue = bevy::ecs::system::CommandQueue::default();
+            let mut cmds = Commands::new(&mut queue, &app.world);
+            blueprint.rotated(*by).paste(&mut cmds, map_pos(5, 5));
+            queue.apply(&mut app.world);
+            app.update();
+
+            let (pos, placed, outputs) = app
+                .world
+                .query::<(&MapPos, &Placed, &MultipleOutputs)>()
+                .iter(&app.world)
+                .next()
+                .expect("merger");
+            let (anchor, footprint) = placed.0.footprint().expect("footprint");
+            let mut cells: Vec<MapPos> = footprint.cells_at(anchor).collect();
+            let mut sources: Vec<MapPos> = outputs.outputs.iter().map(|it| *pos + it.pos).collect();
+            cells.sort_by_key(|it| (it.x, it.y));
+            sources.sort_by_key(|it| (it.x, it.y));
+
+            assert_eq!(cells, sources);
+            assert!(outputs.outputs.iter().all(|it| it.dir == *by));
+        }
+    }
+}
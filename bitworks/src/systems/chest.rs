@@ -0,0 +1,49 @@
+use bevy::prelude::*;
+
+use crate::{
+    try_push_item_to_input, BeltItem, Inventory, ItemInput, ItemRegistry, SimulationStage,
+};
+
+pub const CHEST_SLOTS: usize = 16;
+
+pub struct ChestPlugin;
+impl Plugin for ChestPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(
+            SimulationStage,
+            chest_system.system().label("chest").after("splitter"),
+        );
+    }
+}
+
+/// Stores items from its input in its `Inventory`
+/// and puts them onto its output belt if it has one.
+#[derive(Default)]
+pub struct Chest {
+    pub output: Option<Entity>,
+}
+
+pub fn chest_system(
+    mut chests: Query<(&Chest, &mut Inventory, &mut ItemInput)>,
+    mut outputs: Query<&mut ItemInput, Without<Chest>>,
+    registry: Res<ItemRegistry>,
+) {
+    for (chest, mut inventory, mut input) in chests.iter_mut() {
+        // a full chest leaves the items on the belt
+        while let Some(item) = input.oldest_item().map(|it| it.item) {
+            if inventory.insert(item, &registry) {
+                input.pop_oldest_item();
+            } else {
+                break;
+            }
+        }
+
+        let output = chest.output.and_then(|output| outputs.get_mut(output).ok());
+        if let (Some(mut output), Some(item)) = (output, inventory.peek(None)) {
+            let mut belt_item = BeltItem::new(0.0, item);
+            if try_push_item_to_input(&mut belt_item, &mut output, &registry) {
+                inventory.take(None);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{Belt, BeltLine, ItemInput, MapCache, MapLayer, MapPos};
+
+/// cells along each side of a chunk
+pub const CHUNK_SIZE: i32 = 32;
+
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl ChunkPos {
+    pub fn of(pos: &MapPos) -> Self {
+        Self {
+            x: pos.x.div_euclid(CHUNK_SIZE),
+            y: pos.y.div_euclid(CHUNK_SIZE),
+        }
+    }
+
+    /// the lowest cell of the chunk
+    pub fn min(&self) -> MapPos {
+        MapPos::new(self.x * CHUNK_SIZE, self.y * CHUNK_SIZE)
+    }
+
+    /// the highest cell of the chunk
+    pub fn max(&self) -> MapPos {
+        self.min().add_xy(CHUNK_SIZE - 1, CHUNK_SIZE - 1)
+    }
+}
+
+/// `CHUNK_SIZE` x `CHUNK_SIZE` cells with at most one entity each.
+pub struct Chunk {
+    cells: Vec<Option<Entity>>,
+    len: usize,
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self {
+            cells: vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize],
+            len: 0,
+        }
+    }
+}
+
+impl Chunk {
+    fn index(pos: &MapPos) -> usize {
+        let x = pos.x.rem_euclid(CHUNK_SIZE);
+        let y = pos.y.rem_euclid(CHUNK_SIZE);
+        (y * CHUNK_SIZE + x) as usize
+    }
+
+    pub fn get(&self, pos: &MapPos) -> Option<Entity> {
+        self.cells[Self::index(pos)]
+    }
+
+    pub fn insert(&mut self, pos: &MapPos, entity: Entity) -> Option<Entity> {
+        let old = self.cells[Self::index(pos)].replace(entity);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, pos: &MapPos) -> Option<Entity> {
+        let old = self.cells[Self::index(pos)].take();
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// row by row from the lowest cell
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.cells.iter().flatten().copied()
+    }
+}
+
+/// Cells grouped into chunks, only chunks with something in them are stored.
+#[derive(Default)]
+pub struct ChunkMap {
+    chunks: HashMap<ChunkPos, Chunk>,
+}
+
+impl ChunkMap {
+    pub fn get(&self, pos: &MapPos) -> Option<Entity> {
+        self.chunks.get(&ChunkPos::of(pos))?.get(pos)
+    }
+
+    pub fn insert(&mut self, pos: MapPos, entity: Entity) -> Option<Entity> {
+        self.chunks
+            .entry(ChunkPos::of(&pos))
+            .or_default()
+            .insert(&pos, entity)
+    }
+
+    /// an empty chunk is dropped
+    pub fn remove(&mut self, pos: &MapPos) -> Option<Entity> {
+        let chunk_pos = ChunkPos::of(pos);
+        let chunk = self.chunks.get_mut(&chunk_pos)?;
+        let old = chunk.remove(pos);
+        if chunk.is_empty() {
+            self.chunks.remove(&chunk_pos);
+        }
+        old
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&ChunkPos, &Chunk)> {
+        self.chunks.iter()
+    }
+
+    pub fn chunk(&self, chunk_pos: &ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(chunk_pos)
+    }
+
+    /// entities within `min` and `max`, both inclusive, only stored chunks are visited
+    pub fn in_rect(&self, min: MapPos, max: MapPos) -> impl Iterator<Item = (MapPos, Entity)> + '_ {
+        let (low, high) = (ChunkPos::of(&min), ChunkPos::of(&max));
+
+        (low.y..=high.y)
+            .flat_map(move |y| (low.x..=high.x).map(move |x| ChunkPos { x, y }))
+            .filter_map(move |chunk_pos| Some((chunk_pos, self.chunks.get(&chunk_pos)?)))
+            .flat_map(move |(chunk_pos, chunk)| {
+                // the part of the rect inside of this chunk
+                let (from, to) = (chunk_pos.min(), chunk_pos.max());
+                let (from_x, to_x) = (from.x.max(min.x), to.x.min(max.x));
+                let (from_y, to_y) = (from.y.max(min.y), to.y.min(max.y));
+
+                (from_y..=to_y)
+                    .flat_map(move |y| (from_x..=to_x).map(move |x| MapPos::new(x, y)))
+                    .filter_map(move |pos| Some((pos, chunk.get(&pos)?)))
+            })
+    }
+}
+
+/// Chunks with a belt which carries or receives items,
+/// belts in all other chunks are asleep and not even visited by the belt systems.
+/// A chunk wakes up when an item enters one of its belts and sleeps when the last one leaves.
+#[derive(Default)]
+pub struct AwakeChunks {
+    /// number of belts with items per chunk
+    awake: HashMap<ChunkPos, usize>,
+    /// belts with items and the chunk they keep awake
+    busy: HashMap<Entity, ChunkPos>,
+}
+
+impl AwakeChunks {
+    pub fn is_awake(&self, pos: &MapPos) -> bool {
+        self.awake.contains_key(&ChunkPos::of(pos))
+    }
+
+    pub fn len(&self) -> usize {
+        self.awake.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.awake.is_empty()
+    }
+
+    /// the entities of `MapLayer::Belts` in awake chunks, in the same order every run
+    pub fn belts<'a>(&'a self, map: &'a MapCache) -> impl Iterator<Item = Entity> + 'a {
+        let mut chunks: Vec<ChunkPos> = self.awake.keys().copied().collect();
+        chunks.sort_by_key(|it| (it.y, it.x));
+        let layer = map.layer(MapLayer::Belts);
+        chunks
+            .into_iter()
+            .filter_map(move |chunk_pos| layer?.chunk(&chunk_pos))
+            .flat_map(Chunk::entities)
+    }
+
+    /// `chunk` is the chunk of `belt` if it has items
+    fn set_busy(&mut self, belt: Entity, chunk: Option<ChunkPos>) {
+        let old = match chunk {
+            Some(chunk) => self.busy.insert(belt, chunk),
+            None => self.busy.remove(&belt),
+        };
+        if old == chunk {
+            return;
+        }
+        if let Some(old) = old {
+            if let Some(count) = self.awake.get_mut(&old) {
+                *count -= 1;
+                if *count == 0 {
+                    self.awake.remove(&old);
+                }
+            }
+        }
+        if let Some(chunk) = chunk {
+            *self.awake.entry(chunk).or_default() += 1;
+        }
+    }
+}
+
+/// Only looks at belts which changed or had items, the others stay as they are.
+pub fn chunk_activity_system(
+    mut awake: ResMut<AwakeChunks>,
+    mut belts: QuerySet<(
+        Query<Entity, (With<MapPos>, Or<(Changed<Belt>, Changed<ItemInput>)>)>,
+        Query<(&MapPos, &Belt, &mut ItemInput, Option<&BeltLine>)>,
+    )>,
+) {
+    let mut candidates: Vec<Entity> = belts.q0().iter().collect();
+    candidates.extend(awake.busy.keys().copied());
+
+    for entity in candidates {
+        let chunk = match belts.q1_mut().get_mut(entity) {
+            Ok((pos, belt, _, _)) if !belt.is_empty() => Some(ChunkPos::of(pos)),
+            Ok((pos, _, item_input, _)) if !item_input.is_empty() => Some(ChunkPos::of(pos)),
+            Ok((_, belt, mut item_input, line)) => {
+                // what `belt_input_system` would find on an empty belt, it is skipped while asleep
+                let (length, side_loadable) = (belt.total_length(), line.is_none());
+                if !item_input.is_reset(length, side_loadable) {
+                    item_input.reset_space(length, side_loadable);
+                }
+                None
+            }
+            Err(_) => None,
+        };
+        awake.set_busy(entity, chunk);
+    }
+}
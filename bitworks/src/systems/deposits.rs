@@ -0,0 +1,131 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{ItemId, ItemRegistry, MapPos, SimRng};
+
+/// deposits are generated in a square of this many tiles around the origin
+pub const WORLD_GEN_RADIUS: i32 = 32;
+/// tiles between the lattice points of the noise, roughly the size of a patch
+const NOISE_SCALE: f32 = 8.0;
+/// noise above this gets a deposit
+const PATCH_THRESHOLD: f32 = 0.7;
+const MIN_AMOUNT: u32 = 100;
+const MAX_AMOUNT: u32 = 1000;
+
+pub struct DepositsPlugin;
+impl Plugin for DepositsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(Deposits::default())
+            .add_startup_system(generate_deposits_system.system().label("deposits"));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deposit {
+    pub item: ItemId,
+    pub amount: u32,
+}
+
+/// An entity showing the deposit at its `MapPos`, cached in `MapLayer::Resources`.
+pub struct DepositTile;
+
+/// Ore under the map, at most one deposit per `MapPos`.
+#[derive(Default)]
+pub struct Deposits {
+    deposits: HashMap<MapPos, Deposit>,
+}
+
+impl Deposits {
+    pub fn at(&self, pos: &MapPos) -> Option<&Deposit> {
+        self.deposits.get(pos)
+    }
+
+    pub fn insert(&mut self, pos: MapPos, deposit: Deposit) {
+        self.deposits.insert(pos, deposit);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&MapPos, &Deposit)> {
+        self.deposits.iter()
+    }
+
+    /// takes one item out of the deposit, an empty deposit is gone
+    pub fn mine(&mut self, pos: &MapPos) -> Option<ItemId> {
+        let deposit = self.deposits.get_mut(pos)?;
+        let item = deposit.item;
+        deposit.amount -= 1;
+        if deposit.amount == 0 {
+            self.deposits.remove(pos);
+        }
+        Some(item)
+    }
+
+    /// scatters patches of every ore with one noise field per ore,
+    /// the same seed gives the same deposits
+    pub fn generate(&mut self, seed: u64, radius: i32, ores: &[ItemId]) {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                let pos = MapPos::new(x, y);
+                let (fx, fy) = (x as f32 / NOISE_SCALE, y as f32 / NOISE_SCALE);
+
+                let best = ores
+                    .iter()
+                    .enumerate()
+                    .map(|(index, ore)| {
+                        (*ore, value_noise(seed.wrapping_add(index as u64), fx, fy))
+                    })
+                    .filter(|(_, noise)| *noise > PATCH_THRESHOLD)
+                    .max_by(|a, b| a.1.total_cmp(&b.1));
+
+                if let Some((item, noise)) = best {
+                    let richness = (noise - PATCH_THRESHOLD) / (1.0 - PATCH_THRESHOLD);
+                    let amount = MIN_AMOUNT + (richness * (MAX_AMOUNT - MIN_AMOUNT) as f32) as u32;
+                    self.insert(pos, Deposit { item, amount });
+                }
+            }
+        }
+    }
+}
+
+pub fn generate_deposits_system(
+    mut deposits: ResMut<Deposits>,
+    rng: Res<SimRng>,
+    registry: Res<ItemRegistry>,
+) {
+    let ores: Vec<ItemId> = registry
+        .ids()
+        .filter(|item| registry.get(*item).ore)
+        .collect();
+    deposits.generate(rng.seed(), WORLD_GEN_RADIUS, &ores);
+}
+
+/// smoothly interpolated random values on a lattice, between 0 and 1
+pub fn value_noise(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+    let (x0, y0) = (x0 as i32, y0 as i32);
+
+    let top = lerp(lattice(seed, x0, y0), lattice(seed, x0 + 1, y0), tx);
+    let bottom = lerp(lattice(seed, x0, y0 + 1), lattice(seed, x0 + 1, y0 + 1), tx);
+    lerp(top, bottom, ty)
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// a random value for a lattice point, mixed like splitmix64
+fn lattice(seed: u64, x: i32, y: i32) -> f32 {
+    let mut h = seed
+        ^ (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^= h >> 31;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
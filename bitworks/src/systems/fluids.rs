@@ -0,0 +1,238 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{CompassDir, ItemId, MapCache, MapLayer, MapPos, SimulationStage, TICK_SECONDS};
+
+pub const PIPE_CAPACITY: f32 = 100.0;
+pub const TANK_CAPACITY: f32 = 2500.0;
+/// units per second
+pub const PUMP_RATE: f32 = 20.0;
+/// share of the difference in fill level two connected boxes even out per tick
+const FLOW_FACTOR: f32 = 0.25;
+
+pub struct FluidPlugin;
+impl Plugin for FluidPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(
+            CoreStage::PreUpdate,
+            fluid_hookup_system
+                .system()
+                .label("fluid_hookup")
+                .after("io_hookup"),
+        )
+        .add_system_to_stage(
+            SimulationStage,
+            fluid_flow_system
+                .system()
+                .label("fluid")
+                .after("splitter")
+                .before("assembler"),
+        );
+    }
+}
+
+/// Holds one fluid at a time, fluids are items of the `ItemRegistry` marked as `fluid`.
+#[derive(Clone, Debug)]
+pub struct FluidBox {
+    pub fluid: Option<ItemId>,
+    pub amount: f32,
+    pub capacity: f32,
+    /// only this fluid can flow in
+    pub filter: Option<ItemId>,
+}
+
+impl FluidBox {
+    pub fn new(capacity: f32) -> Self {
+        Self {
+            fluid: None,
+            amount: 0.0,
+            capacity,
+            filter: None,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: Option<ItemId>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn level(&self) -> f32 {
+        self.amount / self.capacity
+    }
+
+    pub fn accepts(&self, fluid: ItemId) -> bool {
+        self.fluid.map_or(true, |it| it == fluid) && self.filter.map_or(true, |it| it == fluid)
+    }
+
+    /// puts in as much as fits, returns the amount put in
+    pub fn insert(&mut self, fluid: ItemId, amount: f32) -> f32 {
+        if !self.accepts(fluid) {
+            return 0.0;
+        }
+        let amount = amount.min(self.capacity - self.amount).max(0.0);
+        if amount > 0.0 {
+            self.fluid = Some(fluid);
+            self.amount += amount;
+        }
+        amount
+    }
+
+    /// takes `amount` only if there is that much
+    pub fn take(&mut self, amount: f32) -> bool {
+        if self.fluid.is_none() || self.amount < amount {
+            return false;
+        }
+        self.amount -= amount;
+        if self.amount <= f32::EPSILON {
+            self.amount = 0.0;
+            self.fluid = None;
+        }
+        true
+    }
+}
+
+pub struct FluidConnection {
+    /// relative to the entity's `MapPos`
+    pub pos: MapPos,
+    pub dir: CompassDir,
+    pub entity: Option<Entity>,
+}
+
+/// Sides of a `FluidBox` other boxes connect to, connected boxes form a fluid system.
+pub struct FluidConnections {
+    pub connections: Vec<FluidConnection>,
+}
+
+impl FluidConnections {
+    pub fn new(entries: &[(MapPos, CompassDir)]) -> Self {
+        Self {
+            connections: entries
+                .iter()
+                .map(|(pos, dir)| FluidConnection {
+                    pos: *pos,
+                    dir: *dir,
+                    entity: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// a connection on every side of a single cell
+    pub fn all_sides() -> Self {
+        let origin = MapPos::new(0, 0);
+        Self::new(&[
+            (origin, CompassDir::N),
+            (origin, CompassDir::E),
+            (origin, CompassDir::S),
+            (origin, CompassDir::W),
+        ])
+    }
+}
+
+pub struct Pipe;
+
+pub struct Tank;
+
+/// Fills its `FluidBox` with `fluid` out of nothing.
+pub struct Pump {
+    pub fluid: ItemId,
+    /// units per second
+    pub rate: f32,
+}
+
+/// Connects two boxes when both have a connection facing each other,
+/// like `input_output_hookup_system` does for items.
+pub fn fluid_hookup_system(
+    mut boxes: Query<(Entity, &MapPos, &mut FluidConnections)>,
+    map: Res<MapCache>,
+) {
+    // cell, direction and if it is connected already, for every connection
+    let sides: HashMap<Entity, Vec<(MapPos, CompassDir, bool)>> = boxes
+        .iter_mut()
+        .map(|(entity, pos, connections)| {
+            let sides = connections
+                .connections
+                .iter()
+                .map(|it| (*pos + it.pos, it.dir, it.entity.is_some()))
+                .collect();
+            (entity, sides)
+        })
+        .collect();
+
+    let mut links = Vec::new();
+    for (entity, own_sides) in sides.iter() {
+        for (index, (cell, dir, connected)) in own_sides.iter().enumerate() {
+            if *connected {
+                continue;
+            }
+            let other_pos = cell.step(*dir);
+            let other = match map.at(MapLayer::Pipes, &other_pos) {
+                Some(other) => other,
+                None => continue,
+            };
+
+            let facing = sides.get(&other).map_or(false, |others| {
+                others
+                    .iter()
+                    .any(|(cell, other_dir, _)| *cell == other_pos && *other_dir == dir.opposite())
+            });
+            if facing {
+                links.push((*entity, index, other));
+            }
+        }
+    }
+
+    for (entity, index, other) in links {
+        if let Ok((_, _, mut connections)) = boxes.get_mut(entity) {
+            connections.connections[index].entity = Some(other);
+        }
+    }
+}
+
+pub fn fluid_flow_system(
+    pumps: Query<(Entity, &Pump)>,
+    connections: Query<(Entity, &FluidConnections)>,
+    mut boxes: Query<&mut FluidBox>,
+) {
+    for (entity, pump) in pumps.iter() {
+        if let Ok(mut fluid_box) = boxes.get_mut(entity) {
+            fluid_box.insert(pump.fluid, pump.rate * TICK_SECONDS);
+        }
+    }
+
+    // every link once, from the entity with the lower id
+    for (entity, connections) in connections.iter() {
+        for other in connections.connections.iter().filter_map(|it| it.entity) {
+            if other < entity {
+                continue;
+            }
+            let a = boxes.get_mut(entity).map(|it| it.clone());
+            let b = boxes.get_mut(other).map(|it| it.clone());
+            let (a, b) = match (a, b) {
+                (Ok(a), Ok(b)) => (a, b),
+                _ => continue,
+            };
+
+            let flow = FLOW_FACTOR * (a.level() - b.level()) * a.capacity.min(b.capacity);
+            let (from, to, flow) = if flow > 0.0 {
+                (entity, other, flow)
+            } else {
+                (other, entity, -flow)
+            };
+            let source = if from == entity { &a } else { &b };
+            let fluid = match source.fluid {
+                Some(fluid) => fluid,
+                None => continue,
+            };
+
+            let moved = boxes
+                .get_mut(to)
+                .map(|mut to| to.insert(fluid, flow.min(source.amount)))
+                .unwrap_or(0.0);
+            if moved > 0.0 {
+                if let Ok(mut from) = boxes.get_mut(from) {
+                    from.take(moved);
+                }
+            }
+        }
+    }
+}
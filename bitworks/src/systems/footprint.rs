@@ -0,0 +1,47 @@
+use crate::{map_pos, CompassDir, MapPos};
+
+/// The cells a building covers relative to its `MapPos`,
+/// every one of them is in `MapLayer::Buildings` of the `MapCache`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Footprint {
+    pub cells: Vec<MapPos>,
+}
+
+impl Footprint {
+    /// `offsets` of a building facing north, x is to the right and y to the front,
+    /// turned to face `dir`
+    pub fn new(offsets: &[MapPos], dir: CompassDir) -> Self {
+        Self {
+            cells: offsets.iter().map(|it| rotate(*it, dir)).collect(),
+        }
+    }
+
+    pub fn single() -> Self {
+        Self {
+            cells: vec![map_pos(0, 0)],
+        }
+    }
+
+    /// `width` cells to the right and `depth` cells to the front
+    pub fn rect(width: i32, depth: i32, dir: CompassDir) -> Self {
+        let offsets: Vec<MapPos> = (0..depth)
+            .flat_map(|y| (0..width).map(move |x| map_pos(x, y)))
+            .collect();
+        Self::new(&offsets, dir)
+    }
+
+    /// the covered cells of a building at `pos`
+    pub fn cells_at(&self, pos: MapPos) -> impl Iterator<Item = MapPos> + '_ {
+        self.cells.iter().map(move |it| pos + *it)
+    }
+}
+
+/// turns an offset of something facing north to face `dir`
+pub fn rotate(offset: MapPos, dir: CompassDir) -> MapPos {
+    let right = map_pos(0, 0).step(dir.right());
+    let front = map_pos(0, 0).step(dir);
+    map_pos(
+        right.x * offset.x + front.x * offset.y,
+        right.y * offset.x + front.y * offset.y,
+    )
+}
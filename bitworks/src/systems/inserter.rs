@@ -0,0 +1,179 @@
+use bevy::prelude::*;
+
+use crate::{
+    try_push_item_to_input, try_side_load_item_to_input, Assembler, Belt, BeltItem, CompassDir,
+    FusedInto, Inventory, ItemId, ItemInput, ItemRegistry, Lane, MapCache, MapLayer, MapPos,
+    RecipeRegistry, SimulationStage, SimulationTime, SingleInput, TILE_HALFSIZE, TILE_SIZE,
+};
+
+/// seconds to swing from pickup to drop, and the same back
+pub const INSERTER_SWING_TIME: f32 = 0.5;
+
+pub struct InserterPlugin;
+impl Plugin for InserterPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(
+            SimulationStage,
+            inserter_system
+                .system()
+                .label("inserter")
+                .after("assembler")
+                .after("chest"),
+        );
+    }
+}
+
+/// Picks an item up from the cell in `pickup` direction and drops it
+/// into the cell in `drop` direction.
+/// Takes from belts, chests and assembler products,
+/// puts into item inputs of belts and buildings, into chests
+/// or into assemblers which need the item, on any cell they cover.
+pub struct Inserter {
+    pub pickup: CompassDir,
+    pub drop: CompassDir,
+    /// seconds for one way
+    pub swing_time: f32,
+    /// only picks up this item
+    pub filter: Option<ItemId>,
+    /// the item which is carried to the drop side
+    pub hand: Option<ItemId>,
+    /// simulation tick when the swing is done
+    pub next_time: u64,
+}
+
+impl Inserter {
+    pub fn new(pickup: CompassDir, drop: CompassDir, filter: Option<ItemId>) -> Self {
+        Self {
+            pickup,
+            drop,
+            swing_time: INSERTER_SWING_TIME,
+            filter,
+            hand: None,
+            next_time: 0,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn inserter_system(
+    mut inserters: Query<(&MapPos, &mut Inserter)>,
+    mut belts: Query<&mut Belt>,
+    fused: Query<&FusedInto>,
+    mut inventories: Query<&mut Inventory>,
+    mut assemblers: Query<&mut Assembler>,
+    mut item_inputs: Query<(&mut ItemInput, Option<&SingleInput>)>,
+    map: Res<MapCache>,
+    registry: Res<ItemRegistry>,
+    recipes: Res<RecipeRegistry>,
+    sim_time: Res<SimulationTime>,
+) {
+    let tick = sim_time.tick();
+
+    for (pos, mut inserter) in inserters.iter_mut() {
+        if inserter.next_time > tick {
+            continue;
+        }
+
+        match inserter.hand {
+            None => {
+                let pickup_pos = pos.step(inserter.pickup);
+                let source = map.at(MapLayer::Belts, &pickup_pos);
+                // assemblers are found by any cell they cover
+                let building = map.at(MapLayer::Buildings, &pickup_pos);
+                let filter = inserter.filter;
+
+                // a fused belt has its items in the first belt of its line
+                let belt_entity = source.map(|it| fused.get(it).map_or(it, |it| it.0));
+                let point = TILE_SIZE * pickup_pos.vec2();
+
+                let picked =
+                    if let Some(mut belt) = belt_entity.and_then(|it| belts.get_mut(it).ok()) {
+                        take_nearest_belt_item(&mut belt, point, filter)
+                    } else if let Some(mut inventory) =
+                        source.and_then(|it| inventories.get_mut(it).ok())
+                    {
+                        inventory.take(filter)
+                    } else if let Some(mut assembler) =
+                        building.and_then(|it| assemblers.get_mut(it).ok())
+                    {
+                        take_product(&mut assembler, filter)
+                    } else {
+                        None
+                    };
+
+                if picked.is_some() {
+                    inserter.hand = picked;
+                    inserter.next_time = sim_time.tick_after(inserter.swing_time);
+                }
+            }
+            Some(item) => {
+                let drop_pos = pos.step(inserter.drop);
+                let target = map.at(MapLayer::Belts, &drop_pos);
+                // without an input on the cell the building covering it takes the item
+                let building = map.at(MapLayer::Buildings, &drop_pos);
+
+                let dropped = if let Some(mut inventory) =
+                    target.and_then(|it| inventories.get_mut(it).ok())
+                {
+                    inventory.insert(item, &registry)
+                } else if let Some((mut input, single_input)) =
+                    target.and_then(|it| item_inputs.get_mut(it).ok())
+                {
+                    let mut belt_item = BeltItem::new(0.0, item);
+                    match single_input.and_then(|it| it.side_lane(inserter.drop.opposite())) {
+                        Some(lane) => {
+                            try_side_load_item_to_input(&mut belt_item, lane, &mut input, &registry)
+                        }
+                        None => try_push_item_to_input(&mut belt_item, &mut input, &registry),
+                    }
+                } else if let Some(mut inventory) =
+                    building.and_then(|it| inventories.get_mut(it).ok())
+                {
+                    inventory.insert(item, &registry)
+                } else if let Some(mut assembler) =
+                    building.and_then(|it| assemblers.get_mut(it).ok())
+                {
+                    assembler.insert_ingredient(item, &recipes)
+                } else {
+                    false
+                };
+
+                if dropped {
+                    inserter.hand = None;
+                    inserter.next_time = sim_time.tick_after(inserter.swing_time);
+                }
+            }
+        }
+    }
+}
+
+/// the item closest to `point` within half a tile
+fn take_nearest_belt_item(belt: &mut Belt, point: Vec2, filter: Option<ItemId>) -> Option<ItemId> {
+    let length = belt.total_length();
+    let mut nearest: Option<(Lane, usize, f32)> = None;
+
+    for lane in Lane::ALL.iter().copied() {
+        for (index, item) in belt.lane_items(lane, length).enumerate() {
+            if filter.map_or(false, |filter| filter != item.item) {
+                continue;
+            }
+
+            let (location, _) = belt.location_on_path(item.pos);
+            let distance = location.truncate().distance(point);
+            if distance <= TILE_HALFSIZE && nearest.map_or(true, |it| distance < it.2) {
+                nearest = Some((lane, index, distance));
+            }
+        }
+    }
+
+    let (lane, index, _) = nearest?;
+    belt.remove_item(lane, index)
+}
+
+fn take_product(assembler: &mut Assembler, filter: Option<ItemId>) -> Option<ItemId> {
+    let index = assembler
+        .done
+        .iter()
+        .position(|item| filter.map_or(true, |filter| filter == *item))?;
+    Some(assembler.done.remove(index))
+}
@@ -0,0 +1,96 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{ItemId, ItemRegistry};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    pub item: ItemId,
+    pub count: u32,
+}
+
+/// A fixed number of slots, each holds one item type up to its stack size.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Inventory {
+    slots: Vec<Option<Slot>>,
+}
+
+impl Inventory {
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: vec![None; slots],
+        }
+    }
+
+    pub fn slots(&self) -> &[Option<Slot>] {
+        &self.slots
+    }
+
+    pub fn count(&self, item: ItemId) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|slot| slot.item == item)
+            .map(|slot| slot.count)
+            .sum()
+    }
+
+    /// every slot holds `map(item)` instead, the counts stay
+    pub fn map_items(&mut self, mut map: impl FnMut(ItemId) -> ItemId) {
+        for slot in self.slots.iter_mut().flatten() {
+            slot.item = map(slot.item);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    /// a slot `item` can be put in, a stack of the same item before an empty slot
+    fn slot_for(&self, item: ItemId, registry: &ItemRegistry) -> Option<usize> {
+        let stack_size = registry.get(item).stack_size;
+        self.slots
+            .iter()
+            .position(
+                |slot| matches!(slot, Some(slot) if slot.item == item && slot.count < stack_size),
+            )
+            .or_else(|| self.slots.iter().position(Option::is_none))
+    }
+
+    pub fn can_insert(&self, item: ItemId, registry: &ItemRegistry) -> bool {
+        self.slot_for(item, registry).is_some()
+    }
+
+    pub fn insert(&mut self, item: ItemId, registry: &ItemRegistry) -> bool {
+        match self.slot_for(item, registry) {
+            Some(index) => {
+                let slot = self.slots[index].get_or_insert(Slot { item, count: 0 });
+                slot.count += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// the item `take` would return
+    pub fn peek(&self, filter: Option<ItemId>) -> Option<ItemId> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|slot| slot.item)
+            .find(|item| filter.map_or(true, |filter| filter == *item))
+    }
+
+    /// one item of the first slot, matching `filter` if given
+    pub fn take(&mut self, filter: Option<ItemId>) -> Option<ItemId> {
+        let index = self.slots.iter().position(
+            |slot| matches!(slot, Some(slot) if filter.map_or(true, |filter| filter == slot.item)),
+        )?;
+        let slot = self.slots[index].as_mut().expect("found");
+        let item = slot.item;
+        slot.count -= 1;
+        if slot.count == 0 {
+            self.slots[index] = None;
+        }
+        Some(item)
+    }
+}
@@ -1,11 +1,15 @@
 use bevy::prelude::*;
 use bevy_inspector_egui::Inspectable;
 
-use crate::{try_push_item_to_input, BeltItem, Item, ItemInput};
+use crate::{
+    powered_tick, try_push_item_to_input, BeltItem, ItemInput, ItemRegistry, PowerConsumer, SimRng,
+    SimulationTime,
+};
 
 #[derive(Default)]
 pub struct RandomItemGenerator {
-    pub next_time: f64,
+    /// simulation tick
+    pub next_time: u64,
     pub cooldown: f32,
     pub output: Option<Entity>,
 }
@@ -46,26 +50,28 @@ impl Inspectable for RandomItemGenerator {
     }
 
     fn setup(app: &mut AppBuilder) {
-        std::primitive::f64::setup(app);
+        std::primitive::u64::setup(app);
         std::primitive::f32::setup(app);
         Option::<Entity>::setup(app);
     }
 }
 
 pub fn random_item_generator_system(
-    mut generators: Query<&mut RandomItemGenerator>,
+    mut generators: Query<(&mut RandomItemGenerator, Option<&mut PowerConsumer>)>,
     mut item_inputs: Query<&mut ItemInput>,
-    time: Res<Time>,
+    sim_time: Res<SimulationTime>,
+    mut rng: ResMut<SimRng>,
+    registry: Res<ItemRegistry>,
 ) {
-    let time = time.seconds_since_startup();
+    let tick = sim_time.tick();
 
-    for mut generator in generators.iter_mut() {
-        if generator.next_time <= time {
+    for (mut generator, mut power) in generators.iter_mut() {
+        if generator.next_time <= tick && powered_tick(power.as_deref_mut()) {
             if let Some(output) = generator.output {
                 if let Ok(mut item_input) = item_inputs.get_mut(output) {
-                    let mut gen_item = BeltItem::new(0.0, Item::random());
-                    if try_push_item_to_input(&mut gen_item, &mut item_input) {
-                        generator.next_time = time + generator.cooldown as f64;
+                    let mut gen_item = BeltItem::new(0.0, registry.random(&mut rng));
+                    if try_push_item_to_input(&mut gen_item, &mut item_input, &registry) {
+                        generator.next_time = sim_time.tick_after(generator.cooldown);
                     }
                 } else {
                     eprintln!("failed  {:?} output item to {:?}", 1, 2);
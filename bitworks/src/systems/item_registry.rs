@@ -0,0 +1,147 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{SimRng, COLOR_C2, COLOR_P2};
+
+/// Index of an item definition in the `ItemRegistry`, in the order of the items file.
+/// Data files refer to items by their `id` and get it with `ItemRegistry::lookup`,
+/// a saved world keeps the ids of its items to map them again when it is loaded.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ItemId(pub u16);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ItemDef {
+    /// unique name, other data files refer to items with it
+    pub id: String,
+    pub display_name: String,
+    pub color: Color,
+    /// index into the item texture atlas
+    pub sprite_index: u32,
+    pub stack_size: u32,
+    /// half of the space an item takes on a belt
+    pub padding: f32,
+    /// found in deposits on the map
+    #[serde(default)]
+    pub ore: bool,
+    /// flows through pipes instead of being carried on belts
+    #[serde(default)]
+    pub fluid: bool,
+}
+
+/// All item types, loaded from `items.ron`.
+pub struct ItemRegistry {
+    items: Vec<ItemDef>,
+    by_name: HashMap<String, ItemId>,
+    /// what `random` draws from
+    random_items: Vec<ItemId>,
+}
+
+impl ItemRegistry {
+    pub fn new(items: Vec<ItemDef>) -> Self {
+        assert!(!items.is_empty(), "at least one item is needed");
+        let by_name = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| (item.id.clone(), ItemId(index as u16)))
+            .collect();
+        let ids = |keep: &dyn Fn(&ItemDef) -> bool| -> Vec<ItemId> {
+            (0..items.len())
+                .filter(|index| keep(&items[*index]))
+                .map(|index| ItemId(index as u16))
+                .collect()
+        };
+        let mut random_items = ids(&|item| item.ore);
+        if random_items.is_empty() {
+            random_items = ids(&|item| !item.fluid);
+        }
+        Self {
+            items,
+            by_name,
+            random_items,
+        }
+    }
+
+    pub fn from_ron(path: &str) -> Result<Self, ron::Error> {
+        let file = std::fs::File::open(path)?;
+        let items: Vec<ItemDef> = ron::de::from_reader(file)?;
+        Ok(Self::new(items))
+    }
+
+    /// `items.ron` from the working directory or the crate, otherwise the builtin items
+    pub fn load_default() -> Self {
+        Self::from_ron("items.ron")
+            .or_else(|_| Self::from_ron("bitworks/items.ron"))
+            .unwrap_or_else(|err| {
+                warn!("items.ron not loaded, using builtin items: {}", err);
+                Self::default()
+            })
+    }
+
+    pub fn get(&self, id: ItemId) -> &ItemDef {
+        &self.items[id.0 as usize]
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<ItemId> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = ItemId> {
+        (0..self.items.len()).map(|index| ItemId(index as u16))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn padding(&self, id: ItemId) -> f32 {
+        self.get(id).padding
+    }
+
+    pub fn color(&self, id: ItemId) -> Color {
+        self.get(id).color
+    }
+
+    /// any ore, any item but fluids if there are no ores
+    pub fn random(&self, rng: &mut SimRng) -> ItemId {
+        self.random_items[rng.usize(0..self.random_items.len())]
+    }
+}
+
+impl Default for ItemRegistry {
+    fn default() -> Self {
+        let item = |id: &str, display_name: &str, color, ore| ItemDef {
+            id: id.into(),
+            display_name: display_name.into(),
+            color,
+            sprite_index: 0,
+            stack_size: 50,
+            padding: 10.0,
+            ore,
+            fluid: false,
+        };
+        Self::new(vec![
+            item("red", "Red", COLOR_C2, true),
+            item("green", "Green", COLOR_P2, true),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_draws_only_ores() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/items.ron");
+        let registry = ItemRegistry::from_ron(path).unwrap();
+        let mut rng = SimRng::new(7);
+        for _ in 0..100 {
+            assert!(registry.get(registry.random(&mut rng)).ore);
+        }
+    }
+}
@@ -1,9 +1,18 @@
-use bevy::{math::vec2, prelude::*, utils::HashMap};
+use bevy::{
+    math::vec2,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use bevy_inspector_egui::Inspectable;
+use serde::{Deserialize, Serialize};
 
-use crate::{CompassDir, SingleInput};
+use crate::{
+    Assembler, Chest, ChunkMap, CompassDir, DepositTile, FluidConnection, FluidConnections,
+    Footprint, Inserter, Merger, MiningDrill, MultipleOutputs, NullSink, PowerPole, PowerProducer,
+    RandomItemGenerator, SingleInput, Splitter, UndergroundBelt,
+};
 
-#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Hash, Inspectable)]
+#[derive(Default, Debug, PartialEq, Eq, Clone, Copy, Hash, Inspectable, Serialize, Deserialize)]
 pub struct MapPos {
     pub x: i32,
     pub y: i32,
@@ -14,7 +23,9 @@ impl Plugin for MapPlugin {
     fn build(&self, app: &mut AppBuilder) {
         app.insert_resource(MapCache::default())
             .add_system_to_stage(CoreStage::First, map_pos_apply_transform_system.system())
-            .add_system_to_stage(CoreStage::First, map_cache_system.system());
+            .add_system_to_stage(CoreStage::First, map_cache_system.system())
+            // despawns of any stage are seen before the trackers are cleared
+            .add_system_to_stage(CoreStage::Last, map_cache_gc_system.system());
     }
 }
 
@@ -47,6 +58,14 @@ impl MapPos {
             CompassDir::W => self.add_xy(-1, 0),
         }
     }
+
+    /// the direction to a neighbouring cell
+    pub fn dir_to(&self, other: &MapPos) -> Option<CompassDir> {
+        CompassDir::ALL
+            .iter()
+            .copied()
+            .find(|dir| self.step(*dir) == *other)
+    }
 }
 
 impl From<(i32, i32)> for MapPos {
@@ -63,38 +82,235 @@ impl std::ops::Add for MapPos {
     }
 }
 
+impl std::ops::Sub for MapPos {
+    type Output = MapPos;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.add_xy(-rhs.x, -rhs.y)
+    }
+}
+
 /// (x: i32, y: i32) -> MapPos
 pub fn map_pos<T: Into<i32>>(x: T, y: T) -> MapPos {
     MapPos::new(x.into(), y.into())
 }
 
+/// What a `MapCache` layer holds, every layer has at most one entity per `MapPos`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum MapLayer {
+    /// buildings themselves, by their `MapPos`
+    Buildings,
+    /// belts and every other item input, connected by `input_output_hookup_system`
+    Belts,
+    /// pipes and other fluid connections
+    Pipes,
+    /// ore and other things on the ground
+    Resources,
+    /// markers drawn on top which do not block anything
+    Overlays,
+}
+
+impl MapLayer {
+    pub const ALL: [MapLayer; 5] = [
+        MapLayer::Buildings,
+        MapLayer::Belts,
+        MapLayer::Pipes,
+        MapLayer::Resources,
+        MapLayer::Overlays,
+    ];
+}
+
+/// Marks an entity for `MapLayer::Overlays`.
+pub struct Overlay;
+
 #[derive(Default)]
 pub struct MapCache {
-    pos_cache: HashMap<MapPos, Entity>,
-    entity_cache: HashMap<Entity, MapPos>,
+    layers: HashMap<MapLayer, ChunkMap>,
+    entity_cache: HashMap<Entity, Vec<(MapLayer, MapPos)>>,
 }
 
 impl MapCache {
-    pub fn at(&self, pos: &MapPos) -> Option<Entity> {
-        self.pos_cache.get(pos).map(|x| *x)
+    pub fn at(&self, layer: MapLayer, pos: &MapPos) -> Option<Entity> {
+        self.layers.get(&layer)?.get(pos)
+    }
+
+    pub fn layer(&self, layer: MapLayer) -> Option<&ChunkMap> {
+        self.layers.get(&layer)
+    }
+
+    /// entities of `layer` within `min` and `max`, both inclusive
+    pub fn entities_in_rect(
+        &self,
+        layer: MapLayer,
+        min: MapPos,
+        max: MapPos,
+    ) -> impl Iterator<Item = (MapPos, Entity)> + '_ {
+        self.layers
+            .get(&layer)
+            .into_iter()
+            .flat_map(move |cells| cells.in_rect(min, max))
+    }
+
+    /// layers and cells `entity` is cached at
+    pub fn cells_of(&self, entity: Entity) -> &[(MapLayer, MapPos)] {
+        self.entity_cache
+            .get(&entity)
+            .map_or(&[], |it| it.as_slice())
+    }
+
+    pub fn insert(&mut self, layer: MapLayer, pos: MapPos, entity: Entity) {
+        self.layers.entry(layer).or_default().insert(pos, entity);
+        self.entity_cache
+            .entry(entity)
+            .or_default()
+            .push((layer, pos));
+    }
+
+    /// nothing which blocks building is at `pos`
+    pub fn is_free(&self, pos: &MapPos) -> bool {
+        [MapLayer::Buildings, MapLayer::Belts, MapLayer::Pipes]
+            .iter()
+            .all(|layer| self.at(*layer, pos).is_none())
+    }
+
+    /// takes `entity` out of every layer, cells taken over by another entity are kept
+    pub fn remove(&mut self, entity: Entity) {
+        for (layer, pos) in self.entity_cache.remove(&entity).unwrap_or_default() {
+            if let Some(cells) = self.layers.get_mut(&layer) {
+                if cells.get(&pos) == Some(entity) {
+                    cells.remove(&pos);
+                }
+            }
+        }
     }
 }
 
 pub fn map_cache_system(
     mut map: ResMut<MapCache>,
-    pos: Query<(Entity, &MapPos), (With<SingleInput>, Added<MapPos>)>,
+    belts: Query<
+        (Entity, &MapPos),
+        (
+            Or<(With<SingleInput>, With<UndergroundBelt>)>,
+            Changed<MapPos>,
+        ),
+    >,
+    buildings: Query<
+        (Entity, &MapPos, Option<&Footprint>),
+        (
+            Or<(
+                With<Footprint>,
+                With<Merger>,
+                With<Splitter>,
+                With<Assembler>,
+                With<Chest>,
+                With<Inserter>,
+                With<MiningDrill>,
+                With<RandomItemGenerator>,
+                With<NullSink>,
+                With<PowerPole>,
+                With<PowerProducer>,
+            )>,
+            Changed<MapPos>,
+        ),
+    >,
+    pipes: Query<(Entity, &MapPos), (With<FluidConnections>, Changed<MapPos>)>,
+    resources: Query<(Entity, &MapPos), (With<DepositTile>, Changed<MapPos>)>,
+    overlays: Query<(Entity, &MapPos), (With<Overlay>, Changed<MapPos>)>,
+    mut outputs: Query<(Entity, &mut MultipleOutputs)>,
+    mut fluid_connections: Query<(Entity, &mut FluidConnections)>,
 ) {
-    for (e, pos) in pos.iter() {
-        map.entity_cache.insert(e, (*pos).clone());
-        map.pos_cache.insert(pos.clone(), e);
+    let layers = belts
+        .iter()
+        .map(|it| (MapLayer::Belts, it))
+        .chain(pipes.iter().map(|it| (MapLayer::Pipes, it)))
+        .chain(resources.iter().map(|it| (MapLayer::Resources, it)))
+        .chain(overlays.iter().map(|it| (MapLayer::Overlays, it)))
+        .map(|(layer, (e, pos))| (layer, e, *pos))
+        // buildings cover every cell of their footprint
+        .chain(buildings.iter().flat_map(|(e, pos, footprint)| {
+            let cells: Vec<MapPos> = match footprint {
+                Some(footprint) => footprint.cells_at(*pos).collect(),
+                None => vec![*pos],
+            };
+            cells
+                .into_iter()
+                .map(move |cell| (MapLayer::Buildings, e, cell))
+        }))
+        .collect::<Vec<_>>();
+
+    // moved entities are taken out of their old cells first
+    let moved: HashSet<Entity> = layers
+        .iter()
+        .map(|(_, e, _)| *e)
+        .filter(|e| !map.cells_of(*e).is_empty())
+        .collect();
+    for e in moved.iter() {
+        map.remove(*e);
     }
+
+    for (layer, e, pos) in layers {
+        map.insert(layer, pos, e);
+    }
+
+    unlink(&moved, &mut outputs, &mut fluid_connections);
 }
 
-pub fn _map_cache_gc_system(mut map: ResMut<MapCache>, removed: RemovedComponents<MapPos>) {
+/// Keeps the cache free of despawned entities and unlinks their neighbours.
+pub fn map_cache_gc_system(
+    mut map: ResMut<MapCache>,
+    removed: RemovedComponents<MapPos>,
+    mut outputs: Query<(Entity, &mut MultipleOutputs)>,
+    mut fluid_connections: Query<(Entity, &mut FluidConnections)>,
+) {
+    let removed: HashSet<Entity> = removed.iter().collect();
     for e in removed.iter() {
-        map.entity_cache
-            .remove(&e)
-            .and_then(|pos| map.pos_cache.remove(&pos));
+        map.remove(*e);
+    }
+
+    unlink(&removed, &mut outputs, &mut fluid_connections);
+}
+
+/// Resets outputs and fluid connections of and to `entities`,
+/// so the hookup systems link them again with whatever is there now.
+fn unlink(
+    entities: &HashSet<Entity>,
+    outputs: &mut Query<(Entity, &mut MultipleOutputs)>,
+    fluid_connections: &mut Query<(Entity, &mut FluidConnections)>,
+) {
+    if entities.is_empty() {
+        return;
+    }
+    let is_unlinked = |own: bool, entity: Option<Entity>| {
+        own || entity.map_or(false, |it| entities.contains(&it))
+    };
+
+    // NOTE only deref mutably when something changes, hookups react to changes
+    for (entity, mut outputs) in outputs.iter_mut() {
+        let own = entities.contains(&entity);
+        if outputs
+            .outputs
+            .iter()
+            .any(|it| it.entity.is_some() && is_unlinked(own, it.entity))
+        {
+            for output in outputs.outputs.iter_mut() {
+                if is_unlinked(own, output.entity) {
+                    output.entity = None;
+                    output.lane = None;
+                }
+            }
+        }
+    }
+
+    for (entity, mut connections) in fluid_connections.iter_mut() {
+        let own = entities.contains(&entity);
+        let linked = |it: &FluidConnection| it.entity.is_some() && is_unlinked(own, it.entity);
+        if connections.connections.iter().any(linked) {
+            for connection in connections.connections.iter_mut() {
+                if is_unlinked(own, connection.entity) {
+                    connection.entity = None;
+                }
+            }
+        }
     }
 }
 
@@ -1,12 +1,13 @@
 use bevy::prelude::*;
 
-use crate::{Belt, ItemInput};
+use crate::{powered_tick, Belt, ItemInput, ItemRegistry, PowerConsumer, SimulationTime};
 
 pub struct Merger {
     pub inputs: Vec<Entity>,
     pub outputs: Vec<Entity>,
 
-    pub next_time: f64,
+    /// simulation tick
+    pub next_time: u64,
     pub cooldown: f32,
     pub items_per_step: usize,
     pub input_cursor: usize,
@@ -14,15 +15,16 @@ pub struct Merger {
 }
 
 pub fn merger_system(
-    mut mergers: Query<&mut Merger>,
+    mut mergers: Query<(&mut Merger, Option<&mut PowerConsumer>)>,
     mut inputs: Query<&mut ItemInput>,
     mut belts: Query<&mut Belt>,
-    time: Res<Time>,
+    sim_time: Res<SimulationTime>,
+    registry: Res<ItemRegistry>,
 ) {
-    let time = time.seconds_since_startup();
+    let tick = sim_time.tick();
 
-    for mut merger in mergers.iter_mut() {
-        if merger.next_time <= time {
+    for (mut merger, mut power) in mergers.iter_mut() {
+        if merger.next_time <= tick && powered_tick(power.as_deref_mut()) {
             merger
                 .inputs
                 .drain_filter(|it| inputs.get_mut(*it).is_err());
@@ -64,7 +66,7 @@ pub fn merger_system(
                             let output_e = *merger.outputs.get(index).expect("checked");
                             let mut belt = belts.get_mut(output_e).expect("checked");
 
-                            if belt.is_space(&item) {
+                            if belt.is_space(&item, &registry) {
                                 if let Some(item) = input.pop_oldest_item() {
                                     belt.add_item(item);
                                     merger.output_cursor = (index + 1) % out_len;
@@ -89,7 +91,7 @@ pub fn merger_system(
             }
 
             if did_something {
-                merger.next_time = time + merger.cooldown as f64;
+                merger.next_time = sim_time.tick_after(merger.cooldown);
             }
         }
     }
@@ -0,0 +1,87 @@
+use bevy::prelude::*;
+
+use crate::{
+    powered_seconds, try_push_item_to_input, BeltItem, Deposits, ItemInput, ItemRegistry, MapPos,
+    PowerConsumer, SimulationStage, SimulationTime,
+};
+
+/// seconds to mine one item
+pub const MINING_TIME: f32 = 1.0;
+
+pub struct MiningDrillPlugin;
+impl Plugin for MiningDrillPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_to_stage(
+            SimulationStage,
+            mining_drill_system
+                .system()
+                .label("mining_drill")
+                .after("splitter"),
+        );
+    }
+}
+
+/// Mines the deposits under the cells it covers one after another
+/// and puts the ore onto its output belt.
+pub struct MiningDrill {
+    /// covered cells relative to the drill's `MapPos`
+    pub area: Vec<MapPos>,
+    pub output: Option<Entity>,
+    /// simulation tick
+    pub next_time: u64,
+    pub cooldown: f32,
+    pub cursor: usize,
+}
+
+impl MiningDrill {
+    pub fn new(area: Vec<MapPos>) -> Self {
+        Self {
+            area,
+            output: None,
+            next_time: 0,
+            cooldown: MINING_TIME,
+            cursor: 0,
+        }
+    }
+}
+
+pub fn mining_drill_system(
+    mut drills: Query<(&MapPos, &mut MiningDrill, Option<&PowerConsumer>)>,
+    mut item_inputs: Query<&mut ItemInput>,
+    mut deposits: ResMut<Deposits>,
+    registry: Res<ItemRegistry>,
+    sim_time: Res<SimulationTime>,
+) {
+    let tick = sim_time.tick();
+
+    for (pos, mut drill, power) in drills.iter_mut() {
+        if drill.next_time > tick {
+            continue;
+        }
+        let cooldown = match powered_seconds(drill.cooldown, power) {
+            Some(cooldown) => cooldown,
+            None => continue,
+        };
+        let mut output = match drill.output.and_then(|it| item_inputs.get_mut(it).ok()) {
+            Some(output) => output,
+            None => continue,
+        };
+
+        // the next covered cell which still has ore
+        let len = drill.area.len();
+        let cell = (0..len)
+            .map(|offset| (drill.cursor + offset) % len)
+            .map(|index| (index, *pos + drill.area[index]))
+            .find(|(_, cell)| deposits.at(cell).is_some());
+
+        if let Some((index, cell)) = cell {
+            let item = deposits.at(&cell).expect("found").item;
+            let mut belt_item = BeltItem::new(0.0, item);
+            if try_push_item_to_input(&mut belt_item, &mut output, &registry) {
+                deposits.mine(&cell);
+                drill.cursor = (index + 1) % len;
+                drill.next_time = sim_time.tick_after(cooldown);
+            }
+        }
+    }
+}
@@ -28,11 +28,77 @@ pub use simple_spawner::*;
 mod belt_debug;
 pub use belt_debug::*;
 
+mod simulation;
+pub use simulation::*;
+
+mod sim_rng;
+pub use sim_rng::*;
+
+mod item_registry;
+pub use item_registry::*;
+
 mod belt;
 pub use belt::*;
 
+mod belt_line;
+pub use belt_line::*;
+
 mod merger;
 pub use merger::*;
 
+mod splitter;
+pub use splitter::*;
+
+mod underground_belt;
+pub use underground_belt::*;
+
+mod recipes;
+pub use recipes::*;
+
+mod assembler;
+pub use assembler::*;
+
+mod inventory;
+pub use inventory::*;
+
+mod chest;
+pub use chest::*;
+
+mod inserter;
+pub use inserter::*;
+
+mod deposits;
+pub use deposits::*;
+
+mod mining_drill;
+pub use mining_drill::*;
+
+mod power;
+pub use power::*;
+
+mod fluids;
+pub use fluids::*;
+
+mod footprint;
+pub use footprint::*;
+
+mod chunks;
+pub use chunks::*;
+
+mod save;
+pub use save::*;
+
+mod blueprint;
+pub use blueprint::*;
+
+mod belt_tool;
+pub use belt_tool::*;
+
+mod belt_router;
+pub use belt_router::*;
+
+mod undo;
+pub use undo::*;
+
 mod voxel;
 pub use voxel::*;
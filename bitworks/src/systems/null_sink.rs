@@ -4,20 +4,35 @@ use crate::ItemInput;
 
 pub struct NullSink {
     inputs: Vec<Entity>,
+    consumed: u64,
 }
 
 impl NullSink {
     pub fn new(inputs: &[Entity]) -> Self {
         Self {
             inputs: inputs.into(),
+            consumed: 0,
         }
     }
+
+    /// number of items destroyed so far
+    pub fn consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    /// continues counting from a saved world
+    pub fn set_consumed(&mut self, consumed: u64) {
+        self.consumed = consumed;
+    }
 }
 
 pub fn null_sink_system(mut sinks: Query<&mut NullSink>, mut inputs: Query<&mut ItemInput>) {
     for mut sink in sinks.iter_mut() {
+        let sink = &mut *sink;
+        let consumed = &mut sink.consumed;
         sink.inputs.drain_filter(|entity| {
             if let Ok(mut input) = inputs.get_mut(*entity) {
+                *consumed += input.len() as u64;
                 input.clear_items();
                 false
             } else {
@@ -0,0 +1,238 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{map_pos, Footprint, MapCache, MapLayer, MapPos, SimulationStage};
+
+/// cells a pole reaches in every direction
+pub const POLE_RADIUS: i32 = 3;
+
+pub struct PowerPlugin;
+impl Plugin for PowerPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(PowerNetworks::default())
+            .add_system_to_stage(CoreStage::PostUpdate, power_network_dirty_system.system())
+            .add_system_to_stage(
+                SimulationStage,
+                power_network_system
+                    .system()
+                    .label("power")
+                    .before("generator"),
+            );
+    }
+}
+
+/// Produces watts for every network one of its poles reaches.
+pub struct PowerProducer {
+    pub watts: f32,
+}
+
+/// Needs watts to work at full speed,
+/// buildings without it do not need power at all.
+pub struct PowerConsumer {
+    pub watts: f32,
+    /// share of the demand its network can provide, 0 without a network
+    pub satisfaction: f32,
+    /// share of a tick saved up by `powered_tick`
+    pub budget: f32,
+}
+
+impl PowerConsumer {
+    pub fn new(watts: f32) -> Self {
+        Self {
+            watts,
+            satisfaction: 0.0,
+            budget: 0.0,
+        }
+    }
+}
+
+/// Connects to other poles and to producers and consumers within `radius`.
+pub struct PowerPole {
+    pub radius: i32,
+}
+
+impl Default for PowerPole {
+    fn default() -> Self {
+        Self {
+            radius: POLE_RADIUS,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PowerNetwork {
+    pub poles: usize,
+    pub production: f32,
+    pub demand: f32,
+    pub satisfaction: f32,
+}
+
+/// One entry per group of connected poles,
+/// solved again only when poles, producers or consumers change.
+#[derive(Default, Debug)]
+pub struct PowerNetworks {
+    pub networks: Vec<PowerNetwork>,
+    dirty: bool,
+}
+
+/// `seconds` stretched by missing power, `None` if the building has no power
+pub fn powered_seconds(seconds: f32, consumer: Option<&PowerConsumer>) -> Option<f32> {
+    match consumer {
+        None => Some(seconds),
+        Some(consumer) if consumer.satisfaction > 0.0 => Some(seconds / consumer.satisfaction),
+        Some(_) => None,
+    }
+}
+
+pub fn is_powered(consumer: Option<&PowerConsumer>) -> bool {
+    powered_seconds(0.0, consumer).is_some()
+}
+
+/// Saves up the share of this tick the network provides, true once a whole tick is paid for.
+/// Buildings which work every tick use this instead of `powered_seconds`.
+pub fn powered_tick(consumer: Option<&mut PowerConsumer>) -> bool {
+    let consumer = match consumer {
+        Some(consumer) => consumer,
+        None => return true,
+    };
+    consumer.budget = (consumer.budget + consumer.satisfaction).min(1.0);
+    if consumer.budget >= 1.0 {
+        consumer.budget -= 1.0;
+        true
+    } else {
+        false
+    }
+}
+
+fn in_reach(pole: &MapPos, radius: i32, pos: &MapPos) -> bool {
+    (pole.x - pos.x).abs() <= radius && (pole.y - pos.y).abs() <= radius
+}
+
+/// Runs every frame so no removal is missed on frames without a simulation tick.
+pub fn power_network_dirty_system(
+    changed: Query<
+        (),
+        (
+            Or<(With<PowerPole>, With<PowerProducer>, With<PowerConsumer>)>,
+            Changed<MapPos>,
+        ),
+    >,
+    removed_poles: RemovedComponents<PowerPole>,
+    removed_producers: RemovedComponents<PowerProducer>,
+    removed_consumers: RemovedComponents<PowerConsumer>,
+    mut power_networks: ResMut<PowerNetworks>,
+) {
+    if changed.iter().next().is_some()
+        || removed_poles.iter().next().is_some()
+        || removed_producers.iter().next().is_some()
+        || removed_consumers.iter().next().is_some()
+    {
+        power_networks.dirty = true;
+    }
+}
+
+pub fn power_network_system(
+    poles: Query<(Entity, &MapPos, &PowerPole)>,
+    producers: Query<(&MapPos, &PowerProducer, Option<&Footprint>)>,
+    mut consumers: Query<(&MapPos, &mut PowerConsumer, Option<&Footprint>)>,
+    map: Res<MapCache>,
+    mut power_networks: ResMut<PowerNetworks>,
+) {
+    if !power_networks.dirty {
+        return;
+    }
+    power_networks.dirty = false;
+
+    // group the poles, two poles are connected if one reaches the other
+    let mut network_of: HashMap<Entity, usize> = HashMap::default();
+    let mut networks: Vec<PowerNetwork> = Vec::new();
+    let mut max_radius = 0;
+
+    for (start, start_pos, start_pole) in poles.iter() {
+        if network_of.contains_key(&start) {
+            continue;
+        }
+        let index = networks.len();
+        let mut members = 0;
+        let mut stack = vec![(*start_pos, start_pole.radius)];
+        network_of.insert(start, index);
+
+        while let Some((pos, radius)) = stack.pop() {
+            members += 1;
+            max_radius = max_radius.max(radius);
+            let reach = map.entities_in_rect(
+                MapLayer::Buildings,
+                pos.add_xy(-radius, -radius),
+                pos.add_xy(radius, radius),
+            );
+            for (_, other) in reach {
+                if network_of.contains_key(&other) {
+                    continue;
+                }
+                if let Ok((_, other_pos, other_pole)) = poles.get(other) {
+                    network_of.insert(other, index);
+                    stack.push((*other_pos, other_pole.radius));
+                }
+            }
+        }
+
+        networks.push(PowerNetwork {
+            poles: members,
+            ..Default::default()
+        });
+    }
+
+    // a building reached by poles of different networks belongs to the first one
+    let network_at = |pos: &MapPos, footprint: Option<&Footprint>| {
+        let cells: Vec<MapPos> = match footprint {
+            Some(footprint) => footprint.cells_at(*pos).collect(),
+            None => vec![*pos],
+        };
+        let min = cells
+            .iter()
+            .fold(*pos, |min, it| map_pos(min.x.min(it.x), min.y.min(it.y)));
+        let max = cells
+            .iter()
+            .fold(*pos, |max, it| map_pos(max.x.max(it.x), max.y.max(it.y)));
+        map.entities_in_rect(
+            MapLayer::Buildings,
+            min.add_xy(-max_radius, -max_radius),
+            max.add_xy(max_radius, max_radius),
+        )
+        .filter_map(|(_, other)| {
+            let (_, pole_pos, pole) = poles.get(other).ok()?;
+            cells
+                .iter()
+                .any(|cell| in_reach(pole_pos, pole.radius, cell))
+                .then(|| network_of[&other])
+        })
+        .min()
+    };
+
+    for (pos, producer, footprint) in producers.iter() {
+        if let Some(index) = network_at(pos, footprint) {
+            networks[index].production += producer.watts;
+        }
+    }
+    let mut consumer_networks = Vec::new();
+    for (pos, consumer, footprint) in consumers.iter_mut() {
+        let index = network_at(pos, footprint);
+        if let Some(index) = index {
+            networks[index].demand += consumer.watts;
+        }
+        consumer_networks.push(index);
+    }
+
+    for network in networks.iter_mut() {
+        network.satisfaction = if network.demand > 0.0 {
+            (network.production / network.demand).min(1.0)
+        } else {
+            1.0
+        };
+    }
+
+    for ((_, mut consumer, _), index) in consumers.iter_mut().zip(consumer_networks) {
+        consumer.satisfaction = index.map_or(0.0, |index| networks[index].satisfaction);
+    }
+
+    power_networks.networks = networks;
+}
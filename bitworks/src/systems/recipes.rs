@@ -0,0 +1,96 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::{ticks_from_seconds, ItemId, ItemRegistry};
+
+/// Index of a recipe in the `RecipeRegistry`, in the order of the recipes file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+pub struct RecipeId(pub u16);
+
+/// A recipe as written in `recipes.ron`, items are referred to by their name.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecipeDef {
+    pub id: String,
+    pub inputs: Vec<(String, u32)>,
+    pub outputs: Vec<(String, u32)>,
+    /// seconds
+    pub craft_time: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recipe {
+    pub id: String,
+    pub inputs: Vec<(ItemId, u32)>,
+    pub outputs: Vec<(ItemId, u32)>,
+    pub craft_ticks: u64,
+}
+
+impl Recipe {
+    /// index into `inputs` of this ingredient
+    pub fn input_index(&self, item: ItemId) -> Option<usize> {
+        self.inputs.iter().position(|(it, _)| *it == item)
+    }
+}
+
+/// All recipes, loaded from `recipes.ron` after the `ItemRegistry`.
+#[derive(Default)]
+pub struct RecipeRegistry {
+    recipes: Vec<Recipe>,
+    by_name: HashMap<String, RecipeId>,
+}
+
+impl RecipeRegistry {
+    /// recipes with unknown items are left out
+    pub fn new(defs: Vec<RecipeDef>, items: &ItemRegistry) -> Self {
+        let mut registry = Self::default();
+
+        for def in defs {
+            let resolve = |entries: &[(String, u32)]| {
+                entries
+                    .iter()
+                    .map(|(name, count)| items.lookup(name).map(|id| (id, *count)))
+                    .collect::<Option<Vec<_>>>()
+            };
+
+            match (resolve(&def.inputs), resolve(&def.outputs)) {
+                (Some(inputs), Some(outputs)) => {
+                    let id = RecipeId(registry.recipes.len() as u16);
+                    registry.by_name.insert(def.id.clone(), id);
+                    registry.recipes.push(Recipe {
+                        id: def.id,
+                        inputs,
+                        outputs,
+                        craft_ticks: ticks_from_seconds(def.craft_time).max(1),
+                    });
+                }
+                _ => warn!("recipe {} refers to unknown items", def.id),
+            }
+        }
+
+        registry
+    }
+
+    pub fn from_ron(path: &str, items: &ItemRegistry) -> Result<Self, ron::Error> {
+        let file = std::fs::File::open(path)?;
+        let defs: Vec<RecipeDef> = ron::de::from_reader(file)?;
+        Ok(Self::new(defs, items))
+    }
+
+    /// `recipes.ron` from the working directory or the crate, otherwise no recipes
+    pub fn load_default(items: &ItemRegistry) -> Self {
+        Self::from_ron("recipes.ron", items)
+            .or_else(|_| Self::from_ron("bitworks/recipes.ron", items))
+            .unwrap_or_else(|err| {
+                warn!("recipes.ron not loaded, there are no recipes: {}", err);
+                Self::default()
+            })
+    }
+
+    pub fn get(&self, id: RecipeId) -> &Recipe {
+        &self.recipes[id.0 as usize]
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<RecipeId> {
+        self.by_name.get(name).copied()
+    }
+}
@@ -0,0 +1,702 @@
+use bevy::{prelude::*, transform::hierarchy::despawn_with_children_recursive, utils::HashMap};
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use crate::*;
+
+pub struct SavePlugin;
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system(restore_system.exclusive_system());
+    }
+}
+
+/// Everything needed to continue a run, written as RON.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WorldSave {
+    pub tick: u64,
+    pub seed: u64,
+    pub rng_state: u64,
+    /// the ids of the items by `ItemId` when saved, items are found by them on load
+    #[serde(default)]
+    pub items: Vec<String>,
+    /// `None` if the world has no `Deposits`
+    #[serde(default)]
+    pub deposits: Option<Vec<(MapPos, Deposit)>>,
+    pub buildings: Vec<BuildingSave>,
+}
+
+/// A building as the `Simple` it was spawned from and everything it holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildingSave {
+    pub simple: Simple,
+    /// items on its own belt, a fused belt gets its part of the line
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub belt_items: Vec<BeltItem>,
+    /// items of its own `ItemInput` first, then of the inputs of its cells
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<Vec<BeltItem>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub state: Vec<BuildingState>,
+}
+
+impl BuildingSave {
+    /// `entity` with everything it holds, a belt of a line is split off first
+    pub fn capture(world: &mut World, entity: Entity) -> Option<Self> {
+        unfuse_belt(world, entity);
+        let placed = world.get::<Placed>(entity)?;
+        let simple = placed.simple(world.get::<PowerConsumer>(entity));
+        let belt_items = world
+            .get::<Belt>(entity)
+            .map_or_else(Vec::new, |belt| belt.items().collect());
+        Some(building_save(world, entity, simple, belt_items))
+    }
+
+    fn map_items(&mut self, map: &mut dyn FnMut(ItemId) -> ItemId) {
+        let inputs = self.inputs.iter_mut().flatten();
+        for item in self.belt_items.iter_mut().chain(inputs) {
+            item.item = map(item.item);
+        }
+        for state in self.state.iter_mut() {
+            match state {
+                BuildingState::Chest(inventory) => inventory.map_items(&mut *map),
+                BuildingState::Assembler { done, .. } => {
+                    for item in done.iter_mut() {
+                        *item = map(*item);
+                    }
+                }
+                BuildingState::Inserter { hand, .. } => *hand = hand.map(&mut *map),
+                BuildingState::Fluid { fluid, .. } => *fluid = fluid.map(&mut *map),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// a building which holds nothing yet
+impl From<Simple> for BuildingSave {
+    fn from(simple: Simple) -> Self {
+        Self {
+            simple,
+            belt_items: Vec::new(),
+            inputs: Vec::new(),
+            state: Vec::new(),
+        }
+    }
+}
+
+/// Cursors, timers and contents which differ from a freshly spawned building.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BuildingState {
+    Generator {
+        next_time: u64,
+    },
+    Merger {
+        next_time: u64,
+        input_cursor: usize,
+        output_cursor: usize,
+    },
+    Splitter {
+        next_time: u64,
+        input_cursor: usize,
+        output_cursor: usize,
+    },
+    Sink {
+        consumed: u64,
+    },
+    Chest(Inventory),
+    Assembler {
+        buffer: Vec<u32>,
+        done: Vec<ItemId>,
+        craft_until: Option<u64>,
+        output_cursor: usize,
+    },
+    Inserter {
+        hand: Option<ItemId>,
+        next_time: u64,
+    },
+    MiningDrill {
+        next_time: u64,
+        cursor: usize,
+    },
+    /// its `FluidBox` or the one of its fluid input
+    Fluid {
+        fluid: Option<ItemId>,
+        amount: f32,
+    },
+}
+
+/// Present while a loaded world is spawned, the simulation waits for it.
+pub struct LoadingWorld;
+
+/// On a building of a loaded world or one placed again until its state is restored.
+pub struct Restore(pub BuildingSave);
+
+impl WorldSave {
+    pub fn capture(world: &mut World) -> Self {
+        // fused belts carry no items, the first belt of their line has them
+        let mut belt_items: HashMap<Entity, Vec<BeltItem>> = HashMap::default();
+        for (entity, belt, line) in world
+            .query_filtered::<(Entity, &Belt, Option<&BeltLine>), Without<FusedInto>>()
+            .iter(world)
+        {
+            for mut item in belt.items() {
+                let owner = match line {
+                    Some(line) => {
+                        let (owner, pos) = line.locate(entity, item.pos);
+                        item.pos = pos;
+                        owner
+                    }
+                    None => entity,
+                };
+                belt_items.entry(owner).or_default().push(item);
+            }
+        }
+
+        let placed: Vec<(Entity, Simple)> = world
+            .query::<(Entity, &Placed, Option<&PowerConsumer>)>()
+            .iter(world)
+            .map(|(entity, placed, power)| (entity, placed.simple(power)))
+            .collect();
+
+        let world = &*world;
+        let buildings = placed
+            .into_iter()
+            .map(|(entity, simple)| {
+                let items = belt_items.remove(&entity).unwrap_or_default();
+                building_save(world, entity, simple, items)
+            })
+            .collect();
+
+        let sim_time = world
+            .get_resource::<SimulationTime>()
+            .expect("SimulationPlugin");
+        let rng = world.get_resource::<SimRng>().expect("SimulationPlugin");
+        let items = world
+            .get_resource::<ItemRegistry>()
+            .map_or_else(Vec::new, |registry| {
+                registry
+                    .ids()
+                    .map(|id| registry.get(id).id.clone())
+                    .collect()
+            });
+        let deposits = world.get_resource::<Deposits>().map(|deposits| {
+            deposits
+                .iter()
+                .map(|(pos, deposit)| (*pos, *deposit))
+                .collect()
+        });
+
+        Self {
+            tick: sim_time.tick(),
+            seed: rng.seed(),
+            rng_state: rng.state(),
+            items,
+            deposits,
+            buildings,
+        }
+    }
+
+    /// replaces all buildings of `world`, their state is restored by `restore_system`,
+    /// nothing is replaced if the save holds items the `ItemRegistry` does not know
+    pub fn load(mut self, world: &mut World) -> Result<(), String> {
+        self.map_items(world.get_resource::<ItemRegistry>().expect("BeltPlugin"))?;
+
+        let old: Vec<Entity> = world
+            .query_filtered::<Entity, Or<(With<Placed>, With<Simple>, With<Restore>)>>()
+            .iter(world)
+            .collect();
+        // the new buildings are placed before `map_cache_gc_system` runs
+        for entity in old {
+            despawn_building(world, entity);
+        }
+
+        world
+            .get_resource_mut::<SimulationTime>()
+            .expect("SimulationPlugin")
+            .set_tick(self.tick);
+        world.insert_resource(SimRng::restore(self.seed, self.rng_state));
+        if let Some(saved) = self.deposits {
+            let mut deposits = Deposits::default();
+            for (pos, deposit) in saved {
+                deposits.insert(pos, deposit);
+            }
+            world.insert_resource(deposits);
+        }
+
+        for building in self.buildings {
+            world
+                .spawn()
+                .insert(building.simple.clone())
+                .insert(Restore(building));
+        }
+        world.insert_resource(LoadingWorld);
+        Ok(())
+    }
+
+    /// from the `ItemId`s of `items` to the ones of `registry`
+    fn map_items(&mut self, registry: &ItemRegistry) -> Result<(), String> {
+        let ids = self
+            .items
+            .iter()
+            .map(|name| {
+                registry
+                    .lookup(name)
+                    .ok_or_else(|| format!("unknown item {:?}", name))
+            })
+            .collect::<Result<Vec<ItemId>, String>>()?;
+        let mut unknown = None;
+        let mut map = |item: ItemId| match ids.get(item.0 as usize) {
+            Some(id) => *id,
+            None => {
+                unknown = Some(item);
+                item
+            }
+        };
+
+        for building in self.buildings.iter_mut() {
+            building.map_items(&mut map);
+        }
+        for (_, deposit) in self.deposits.iter_mut().flatten() {
+            deposit.item = map(deposit.item);
+        }
+        match unknown {
+            Some(item) => Err(format!("no item {} in the saved items", item.0)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn read(path: &str) -> Result<Self, ron::Error> {
+        let file = std::fs::File::open(path)?;
+        ron::de::from_reader(file)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), ron::Error> {
+        let ron = ron::ser::to_string_pretty(self, PrettyConfig::new())?;
+        std::fs::write(path, ron)?;
+        Ok(())
+    }
+}
+
+/// An exclusive system which writes the world to `path`.
+pub fn save_world(path: &str) -> impl FnMut(&mut World) + Send + Sync + 'static {
+    let path = path.to_owned();
+    move |world: &mut World| match WorldSave::capture(world).write(&path) {
+        Ok(()) => info!("world saved to {}", path),
+        Err(err) => error!("failed to save world to {}: {}", path, err),
+    }
+}
+
+/// An exclusive system which replaces the world with the one saved at `path`.
+pub fn load_world(path: &str) -> impl FnMut(&mut World) + Send + Sync + 'static {
+    let path = path.to_owned();
+    move |world: &mut World| match WorldSave::read(&path) {
+        Ok(save) => {
+            let tick = save.tick;
+            match save.load(world) {
+                Ok(()) => info!("world loaded from {} at tick {}", path, tick),
+                Err(err) => error!("failed to load world from {}: {}", path, err),
+            }
+        }
+        Err(err) => error!("failed to load world from {}: {}", path, err),
+    }
+}
+
+/// Restores buildings once they are spawned, belt items are in place before
+/// belt lines are fused. When none are left a loaded world continues.
+pub fn restore_system(world: &mut World) {
+    let pending: Vec<(Entity, bool)> = world
+        .query_filtered::<(Entity, Option<&Placed>), With<Restore>>()
+        .iter(world)
+        .map(|(entity, placed)| (entity, placed.is_some()))
+        .collect();
+    if pending.is_empty() {
+        world.remove_resource::<LoadingWorld>();
+        return;
+    }
+
+    // powered buildings are placed a frame later
+    for (entity, _) in pending.into_iter().filter(|(_, placed)| *placed) {
+        if let Some(Restore(building)) = world.entity_mut(entity).remove::<Restore>() {
+            restore(world, entity, building);
+        }
+    }
+}
+
+fn building_save(
+    world: &World,
+    entity: Entity,
+    simple: Simple,
+    belt_items: Vec<BeltItem>,
+) -> BuildingSave {
+    let mut inputs: Vec<Vec<BeltItem>> = input_entities(world, entity)
+        .into_iter()
+        .map(|input| {
+            world
+                .get::<ItemInput>(input)
+                .map_or_else(Vec::new, |it| it.items().to_vec())
+        })
+        .collect();
+    if inputs.iter().all(Vec::is_empty) {
+        inputs.clear();
+    }
+
+    BuildingSave {
+        simple,
+        belt_items,
+        inputs,
+        state: building_state(world, entity),
+    }
+}
+
+fn restore(world: &mut World, entity: Entity, building: BuildingSave) {
+    if let Some(mut belt) = world.get_mut::<Belt>(entity) {
+        for item in building.belt_items {
+            belt.add_item(item);
+        }
+    }
+
+    for (input, items) in input_entities(world, entity)
+        .into_iter()
+        .zip(building.inputs)
+    {
+        if let Some(mut item_input) = world.get_mut::<ItemInput>(input) {
+            item_input.clear_items();
+            item_input.add_items(items);
+        }
+    }
+
+    for state in building.state {
+        restore_state(world, entity, state);
+    }
+}
+
+fn restore_state(world: &mut World, entity: Entity, state: BuildingState) {
+    match state {
+        BuildingState::Generator { next_time } => {
+            if let Some(mut generator) = world.get_mut::<RandomItemGenerator>(entity) {
+                generator.next_time = next_time;
+            }
+        }
+        BuildingState::Merger {
+            next_time,
+            input_cursor,
+            output_cursor,
+        } => {
+            if let Some(mut merger) = world.get_mut::<Merger>(entity) {
+                merger.next_time = next_time;
+                merger.input_cursor = input_cursor;
+                merger.output_cursor = output_cursor;
+            }
+        }
+        BuildingState::Splitter {
+            next_time,
+            input_cursor,
+            output_cursor,
+        } => {
+            if let Some(mut splitter) = world.get_mut::<Splitter>(entity) {
+                splitter.next_time = next_time;
+                splitter.input_cursor = input_cursor;
+                splitter.output_cursor = output_cursor;
+            }
+        }
+        BuildingState::Sink { consumed } => {
+            if let Some(mut sink) = world.get_mut::<NullSink>(entity) {
+                sink.set_consumed(consumed);
+            }
+        }
+        BuildingState::Chest(saved) => {
+            if let Some(mut inventory) = world.get_mut::<Inventory>(entity) {
+                *inventory = saved;
+            }
+        }
+        BuildingState::Assembler {
+            buffer,
+            done,
+            craft_until,
+            output_cursor,
+        } => {
+            if let Some(mut assembler) = world.get_mut::<Assembler>(entity) {
+                assembler.buffer = buffer;
+                assembler.done = done;
+                assembler.craft_until = craft_until;
+                assembler.output_cursor = output_cursor;
+            }
+        }
+        BuildingState::Inserter { hand, next_time } => {
+            if let Some(mut inserter) = world.get_mut::<Inserter>(entity) {
+                inserter.hand = hand;
+                inserter.next_time = next_time;
+            }
+        }
+        BuildingState::MiningDrill { next_time, cursor } => {
+            if let Some(mut drill) = world.get_mut::<MiningDrill>(entity) {
+                drill.next_time = next_time;
+                drill.cursor = cursor;
+            }
+        }
+        BuildingState::Fluid { fluid, amount } => {
+            let target = fluid_box_of(world, entity);
+            if let Some(mut fluid_box) = world.get_mut::<FluidBox>(target) {
+                fluid_box.fluid = fluid;
+                fluid_box.amount = amount;
+            }
+        }
+    }
+}
+
+fn building_state(world: &World, entity: Entity) -> Vec<BuildingState> {
+    let mut state = Vec::new();
+
+    if let Some(generator) = world.get::<RandomItemGenerator>(entity) {
+        state.push(BuildingState::Generator {
+            next_time: generator.next_time,
+        });
+    }
+    if let Some(merger) = world.get::<Merger>(entity) {
+        state.push(BuildingState::Merger {
+            next_time: merger.next_time,
+            input_cursor: merger.input_cursor,
+            output_cursor: merger.output_cursor,
+        });
+    }
+    if let Some(splitter) = world.get::<Splitter>(entity) {
+        state.push(BuildingState::Splitter {
+            next_time: splitter.next_time,
+            input_cursor: splitter.input_cursor,
+            output_cursor: splitter.output_cursor,
+        });
+    }
+    if let Some(sink) = world.get::<NullSink>(entity) {
+        state.push(BuildingState::Sink {
+            consumed: sink.consumed(),
+        });
+    }
+    if let Some(inventory) = world.get::<Inventory>(entity) {
+        state.push(BuildingState::Chest(inventory.clone()));
+    }
+    if let Some(assembler) = world.get::<Assembler>(entity) {
+        state.push(BuildingState::Assembler {
+            buffer: assembler.buffer.clone(),
+            done: assembler.done.clone(),
+            craft_until: assembler.craft_until,
+            output_cursor: assembler.output_cursor,
+        });
+    }
+    if let Some(inserter) = world.get::<Inserter>(entity) {
+        state.push(BuildingState::Inserter {
+            hand: inserter.hand,
+            next_time: inserter.next_time,
+        });
+    }
+    if let Some(drill) = world.get::<MiningDrill>(entity) {
+        state.push(BuildingState::MiningDrill {
+            next_time: drill.next_time,
+            cursor: drill.cursor,
+        });
+    }
+    if let Some(fluid_box) = world.get::<FluidBox>(fluid_box_of(world, entity)) {
+        state.push(BuildingState::Fluid {
+            fluid: fluid_box.fluid,
+            amount: fluid_box.amount,
+        });
+    }
+
+    state
+}
+
+/// Despawns a building with its shapes and the entities of its cells
+/// and takes them out of the `MapCache` right away, so its cells are free again.
+pub fn despawn_building(world: &mut World, entity: Entity) {
+    let mut parts = input_entities(world, entity);
+    parts.extend(world.get::<Assembler>(entity).and_then(|it| it.fluid_input));
+    parts.push(entity);
+
+    // the other belts of its line keep their items
+    unfuse_belt(world, entity);
+    if let Some(mut map) = world.get_resource_mut::<MapCache>() {
+        for part in parts.iter() {
+            map.remove(*part);
+        }
+    }
+    for part in parts {
+        if world.get_entity(part).is_some() {
+            despawn_with_children_recursive(world, part);
+        }
+    }
+}
+
+/// its own `ItemInput` and the inputs of its cells, in the order they are saved
+fn input_entities(world: &World, entity: Entity) -> Vec<Entity> {
+    let mut inputs = Vec::new();
+    if world.get::<ItemInput>(entity).is_some() {
+        inputs.push(entity);
+    }
+    if let Some(merger) = world.get::<Merger>(entity) {
+        inputs.extend(merger.inputs.iter().copied());
+    }
+    if let Some(splitter) = world.get::<Splitter>(entity) {
+        inputs.extend(splitter.inputs.iter().copied());
+    }
+    if let Some(assembler) = world.get::<Assembler>(entity) {
+        inputs.extend(assembler.inputs.iter().copied());
+    }
+    inputs
+}
+
+/// the fluid input of an assembler, otherwise the entity itself
+fn fluid_box_of(world: &World, entity: Entity) -> Entity {
+    world
+        .get::<Assembler>(entity)
+        .and_then(|it| it.fluid_input)
+        .unwrap_or(entity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_app() -> App {
+        let mut app = App::build();
+        app.insert_resource(SimulationTime::stepped(1))
+            .insert_resource(SimRng::new(42))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(SimulationPlugin)
+            .add_plugin(MapPlugin)
+            .add_plugin(SimpleSpawnerPlugin)
+            .add_plugin(BeltInputOutputHookupPlugin)
+            .add_plugin(BeltPlugin)
+            .add_plugin(SavePlugin);
+        app.app
+    }
+
+    fn layout_app(layout: &str, ticks: u64) -> App {
+        let path = format!("{}/layouts/{}", env!("CARGO_MANIFEST_DIR"), layout);
+        let file = std::fs::File::open(path).expect("layout");
+        let simples: Vec<Simple> = ron::de::from_reader(file).expect("layout");
+
+        let mut app = test_app();
+        for simple in simples {
+            app.world.spawn().insert(simple);
+        }
+        while app.world.get_resource::<SimulationTime>().unwrap().tick() < ticks {
+            app.update();
+        }
+        app
+    }
+
+    /// Buildings are sorted, a loaded world does not spawn them in the same order.
+    /// Belts keep the gaps between items, so positions are rounded.
+    fn to_ron(mut save: WorldSave) -> String {
+        for building in save.buildings.iter_mut() {
+            let items = building.inputs.iter_mut().flatten();
+            for item in building.belt_items.iter_mut().chain(items) {
+                item.pos = (item.pos * 1000.0).round() / 1000.0;
+            }
+        }
+        save.buildings
+            .sort_by_cached_key(|it| ron::ser::to_string(it).unwrap());
+        ron::ser::to_string_pretty(&save, PrettyConfig::new()).unwrap()
+    }
+
+    /// saves `app` as RON, loads it into a new app and checks that saving that gives the same
+    fn round_trip(app: &mut App) -> App {
+        let saved = to_ron(WorldSave::capture(&mut app.world));
+
+        let mut loaded = test_app();
+        loaded.update();
+        let save: WorldSave = ron::de::from_str(&saved).unwrap();
+        save.load(&mut loaded.world).unwrap();
+        // spawned and restored, the simulation still waits
+        loaded.update();
+        assert!(loaded.world.get_resource::<LoadingWorld>().is_some());
+        assert_eq!(
+            0,
+            loaded
+                .world
+                .query_filtered::<Entity, With<Restore>>()
+                .iter(&loaded.world)
+                .count()
+        );
+
+        assert_eq!(saved, to_ron(WorldSave::capture(&mut loaded.world)));
+        loaded
+    }
+
+    #[test]
+    fn belts_merger_and_generators_round_trip() {
+        let mut app = layout_app("merger.ron", 600);
+        let world = &mut app.world;
+        let tick = world.get_resource::<SimulationTime>().unwrap().tick();
+        let item = world.get_resource::<ItemRegistry>().unwrap().ids().next();
+
+        let mut inputs = Vec::new();
+        for mut merger in world.query::<&mut Merger>().iter_mut(world) {
+            merger.next_time = tick + 30;
+            merger.input_cursor = 1;
+            merger.output_cursor = 1;
+            inputs.extend(merger.inputs.first().copied());
+        }
+        for input in inputs {
+            let mut item_input = world.get_mut::<ItemInput>(input).unwrap();
+            item_input.add_items(item.map(|item| BeltItem::new(0.0, item)));
+        }
+        for mut generator in world.query::<&mut RandomItemGenerator>().iter_mut(world) {
+            generator.next_time = tick + 10;
+        }
+
+        let save = WorldSave::capture(world);
+        assert!(save.buildings.iter().any(|it| !it.belt_items.is_empty()));
+        assert!(save.buildings.iter().any(|it| !it.inputs.is_empty()));
+        assert!(save
+            .buildings
+            .iter()
+            .any(|it| matches!(it.simple, Simple::ItemGenerator(_, _))));
+
+        round_trip(&mut app);
+    }
+
+    #[test]
+    fn items_are_found_by_their_saved_ids() {
+        let mut app = layout_app("merger.ron", 300);
+        let expected = to_ron(WorldSave::capture(&mut app.world));
+
+        // as if saved by a build with the items in reverse order
+        let mut save: WorldSave = ron::de::from_str(&expected).unwrap();
+        let last = save.items.len() as u16 - 1;
+        save.items.reverse();
+        for building in save.buildings.iter_mut() {
+            building.map_items(&mut |item| ItemId(last - item.0));
+        }
+
+        let mut loaded = test_app();
+        loaded.update();
+        save.load(&mut loaded.world).unwrap();
+        loaded.update();
+        assert_eq!(expected, to_ron(WorldSave::capture(&mut loaded.world)));
+    }
+
+    #[test]
+    fn unknown_items_are_not_loaded() {
+        let mut app = layout_app("merger.ron", 300);
+        let mut save = WorldSave::capture(&mut app.world);
+        save.items[0] = "unobtainium".to_owned();
+
+        let mut loaded = test_app();
+        loaded.update();
+        assert!(save.load(&mut loaded.world).is_err());
+        assert!(loaded.world.get_resource::<LoadingWorld>().is_none());
+    }
+
+    #[test]
+    fn rng_round_trips() {
+        let mut app = layout_app("merger.ron", 120);
+        let mut loaded = round_trip(&mut app);
+
+        let mut rng = app.world.get_resource_mut::<SimRng>().unwrap();
+        let draws: Vec<f32> = (0..8).map(|_| rng.f32()).collect();
+        let mut loaded_rng = loaded.world.get_resource_mut::<SimRng>().unwrap();
+        let loaded_draws: Vec<f32> = (0..8).map(|_| loaded_rng.f32()).collect();
+        assert_eq!(draws, loaded_draws);
+    }
+}
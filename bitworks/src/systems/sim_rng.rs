@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+
+use crate::Config;
+
+/// The only source of randomness for the simulation.
+/// Log or pass the seed along with bug reports to reproduce a run.
+pub struct SimRng {
+    seed: u64,
+    /// seeds the generator for the next draw, saved with the world
+    state: u64,
+}
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// continues a run from a saved world
+    pub fn restore(seed: u64, state: u64) -> Self {
+        Self { seed, state }
+    }
+
+    /// seed from `--seed <u64>` on the command line, then `Config::seed`, otherwise a random one
+    pub fn from_args_or_config(config: Option<&Config>) -> Self {
+        let seed = seed_from_args()
+            .or_else(|| config.and_then(|config| config.seed))
+            .unwrap_or_else(|| fastrand::u64(..));
+        info!("simulation seed {}", seed);
+        Self::new(seed)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn f32(&mut self) -> f32 {
+        self.next().f32()
+    }
+
+    pub fn usize(&mut self, range: std::ops::Range<usize>) -> usize {
+        self.next().usize(range)
+    }
+
+    /// `fastrand::Rng` does not expose its state, so every draw gets its own generator
+    fn next(&mut self) -> fastrand::Rng {
+        let rng = fastrand::Rng::with_seed(self.state);
+        self.state = rng.u64(..);
+        rng
+    }
+}
+
+fn seed_from_args() -> Option<u64> {
+    let mut args = std::env::args().skip_while(|arg| arg != "--seed").skip(1);
+    args.next().and_then(|seed| seed.parse().ok())
+}
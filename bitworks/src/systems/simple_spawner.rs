@@ -1,4 +1,5 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
 
 use crate::*;
 
@@ -10,27 +11,122 @@ impl Plugin for SimpleSpawnerPlugin {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Simple {
     /// pos, out direction
     ItemGenerator(MapPos, CompassDir),
-    /// pos, in direction, out direction
-    Belt(MapPos, CompassDir, CompassDir),
+    /// pos, in direction, out direction, tier which is `Basic` if left out
+    Belt(MapPos, CompassDir, CompassDir, #[serde(default)] BeltTier),
     /// pos, in direction
     NullSink(MapPos, CompassDir),
     // pos of cell 1, output direction, pos of cell 2 is right of output direction
     Merger2x2(MapPos, CompassDir),
+    /// pos of left cell, output direction, right cell is right of output direction, settings
+    Splitter2x1(MapPos, CompassDir, SplitterSettings),
+    /// pos, direction of travel, pairs with the next exit in that direction
+    UndergroundEntrance(MapPos, CompassDir),
+    /// pos, direction of travel
+    UndergroundExit(MapPos, CompassDir),
+    /// pos of the back left cell of 2x2, output direction, recipe name
+    Assembler(MapPos, CompassDir, String),
+    /// pos, in direction, out direction if it puts items onto a belt
+    Chest(MapPos, CompassDir, Option<CompassDir>),
+    /// pos, pickup direction, drop direction, only items of this name
+    Inserter(MapPos, CompassDir, CompassDir, Option<String>),
+    /// pos of the back left cell of 2x2, output direction of the front left cell
+    MiningDrill(MapPos, CompassDir),
+    /// pos, watts it produces
+    PowerPlant(MapPos, f32),
+    /// pos
+    PowerPole(MapPos),
+    /// watts it needs, the building which needs them
+    Powered(f32, Box<Simple>),
+    /// pos, connects to all sides
+    Pipe(MapPos),
+    /// pos, connects to all sides
+    Tank(MapPos),
+    /// pos, output direction, name of the fluid it pumps
+    Pump(MapPos, CompassDir, String),
+}
+
+/// The `Simple` a building was spawned from, a saved world consists of these.
+#[derive(Debug, Clone)]
+pub struct Placed(pub Simple);
+
+impl Placed {
+    /// the `Simple` to spawn it again, wrapped in `Powered` if it is a `PowerConsumer`
+    pub fn simple(&self, power: Option<&PowerConsumer>) -> Simple {
+        match power {
+            Some(consumer) => Simple::Powered(consumer.watts, Box::new(self.0.clone())),
+            None => self.0.clone(),
+        }
+    }
+}
+
+impl Simple {
+    /// where it is placed and the cells it covers, `None` if it is not placed itself
+    pub fn footprint(&self) -> Option<(MapPos, Footprint)> {
+        let two_by_two = [map_pos(0, 0), map_pos(1, 0), map_pos(0, 1), map_pos(1, 1)];
+
+        Some(match self {
+            Simple::Merger2x2(pos, dir) | Simple::Splitter2x1(pos, dir, _) => {
+                (*pos, Footprint::new(&[map_pos(0, 0), map_pos(1, 0)], *dir))
+            }
+            Simple::Assembler(pos, dir, _) | Simple::MiningDrill(pos, dir) => {
+                (*pos, Footprint::new(&two_by_two, *dir))
+            }
+            Simple::ItemGenerator(pos, _)
+            | Simple::Belt(pos, _, _, _)
+            | Simple::NullSink(pos, _)
+            | Simple::UndergroundEntrance(pos, _)
+            | Simple::UndergroundExit(pos, _)
+            | Simple::Chest(pos, _, _)
+            | Simple::Inserter(pos, _, _, _)
+            | Simple::PowerPlant(pos, _)
+            | Simple::PowerPole(pos)
+            | Simple::Pipe(pos)
+            | Simple::Tank(pos)
+            | Simple::Pump(pos, _, _) => (*pos, Footprint::single()),
+            Simple::Powered(_, _) => return None,
+        })
+    }
 }
 
 // fn simple_spawner_system(simples: Query<(Entity, &Simple), Added<Simple>>, mut cmds: Commands) {
 fn simple_spawner_system(
     simples: Query<(Entity, &Simple), Added<Simple>>,
     mut cmds: Commands,
-    belt_atlas: Res<BeltAtlasHandle>,
+    belt_atlas: Option<Res<BeltAtlasHandle>>,
+    recipes: Option<Res<RecipeRegistry>>,
+    items: Option<Res<ItemRegistry>>,
+    map: Res<MapCache>,
 ) {
+    // headless there are no assets, belts are spawned without sprites
+    let belt_atlas = belt_atlas.as_deref();
+    // spawned this frame, not in the map yet
+    let mut claimed: HashSet<MapPos> = HashSet::default();
+
     for (entity, simple) in simples.iter() {
         cmds.entity(entity).remove::<Simple>();
 
+        if let Some((pos, footprint)) = simple.footprint() {
+            let cells: Vec<MapPos> = footprint.cells_at(pos).collect();
+            if cells
+                .iter()
+                .any(|cell| claimed.contains(cell) || !map.is_free(cell))
+            {
+                warn!("{:?} not placed, its cells are occupied", simple);
+                cmds.entity(entity).despawn();
+                continue;
+            }
+            claimed.extend(cells);
+
+            if footprint.cells.len() > 1 {
+                cmds.entity(entity).insert(footprint);
+            }
+            cmds.entity(entity).insert(Placed(simple.clone()));
+        }
+
         match simple {
             Simple::ItemGenerator(pos, out_dir) => {
                 cmds.entity(entity)
@@ -38,7 +134,7 @@ fn simple_spawner_system(
                     .insert(*pos)
                     .insert(RandomItemGenerator {
                         cooldown: 0.0,
-                        next_time: 1.0,
+                        next_time: ticks_from_seconds(1.0),
                         output: None,
                     })
                     .insert(output((0, 0), *out_dir))
@@ -48,30 +144,10 @@ fn simple_spawner_system(
                         4.0,
                     ));
             }
-            Simple::Belt(pos, in_dir, out_dir) => {
-                let pos_vec = pos.vec2();
-                let in_vec = 0.5 * in_dir.vec2();
-                let out_vec = 0.5 * out_dir.vec2();
-                let start = TILE_SIZE * vec3(pos_vec.x + in_vec.x, pos_vec.y + in_vec.y, 0.0);
-                let end = TILE_SIZE * vec3(pos_vec.x + out_vec.x, pos_vec.y + out_vec.y, 0.0);
-                let segment = BeltSegment { start, end };
-
-                cmds.entity(entity)
-                    .insert(Name::new("Belt"))
-                    .insert(*pos)
-                    .insert(Belt {
-                        segments: vec![segment],
-                        items: vec![],
-                        output: None,
-                    })
-                    .insert(ItemInput::new(2))
-                    .insert(input(map_pos(0, 0), *in_dir))
-                    .insert(output((0, 0), *out_dir))
-                    .insert_bundle(SpriteSheetBundle {
-                        sprite: TextureAtlasSprite::new(0),
-                        texture_atlas: belt_atlas.clone(),
-                        ..Default::default()
-                    });
+            Simple::Belt(pos, in_dir, out_dir, tier) => {
+                spawn_belt(
+                    &mut cmds, entity, belt_atlas, *pos, *in_dir, *out_dir, *tier,
+                );
             }
             Simple::NullSink(pos, in_dir) => {
                 cmds.entity(entity)
@@ -112,17 +188,14 @@ fn simple_spawner_system(
                     .insert(GlobalTransform::default())
                     .insert(Merger {
                         cooldown: 0.0,
-                        next_time: 0.0,
+                        next_time: 0,
                         items_per_step: 1,
                         input_cursor: 0,
                         output_cursor: 0,
                         inputs: vec![in1, in2],
                         outputs: vec![],
                     })
-                    .insert(outputs(&[
-                        (map_pos(0, 0), out_dir),
-                        (map_pos(0, -1), out_dir),
-                    ]))
+                    .insert(outputs(&[(map_pos(0, 0), out_dir), (right, out_dir)]))
                     .with_children(|child| {
                         child.spawn().insert_bundle(
                             lyon().rectangle(TILE_SIZE, 2.0 * TILE_SIZE).outlined_pos(
@@ -134,6 +207,371 @@ fn simple_spawner_system(
                         );
                     });
             }
+            Simple::Splitter2x1(pos1, out_dir, settings) => {
+                let filter = match lookup_items(items.as_deref(), settings.filter.as_deref()) {
+                    Ok(filter) => filter,
+                    Err(name) => {
+                        warn!("no item {} for splitter at {:?}", name, pos1);
+                        cmds.entity(entity).despawn();
+                        continue;
+                    }
+                };
+                let pos1 = *pos1;
+                let out_dir = *out_dir;
+                let in_dir = out_dir.opposite();
+                let right = map_pos(0, 0).step(out_dir.right());
+                let pos2 = pos1 + right;
+                let offset = 0.5 * TILE_SIZE * right.vec2();
+
+                let in1 = cmds
+                    .spawn()
+                    .insert(pos1)
+                    .insert(ItemInput::new(2))
+                    .insert(input(map_pos(0, 0), in_dir))
+                    .id();
+                let in2 = cmds
+                    .spawn()
+                    .insert(pos2)
+                    .insert(ItemInput::new(2))
+                    .insert(input(right, in_dir))
+                    .id();
+                cmds.entity(entity)
+                    .insert(Name::new("Splitter"))
+                    .insert(pos1)
+                    .insert(Transform::default())
+                    .insert(GlobalTransform::default())
+                    .insert(Splitter::new([in1, in2], settings.clone(), filter))
+                    .insert(outputs(&[(map_pos(0, 0), out_dir), (right, out_dir)]))
+                    .with_children(|child| {
+                        let (width, height) = match out_dir {
+                            CompassDir::N | CompassDir::S => (2.0 * TILE_SIZE, TILE_SIZE),
+                            CompassDir::E | CompassDir::W => (TILE_SIZE, 2.0 * TILE_SIZE),
+                        };
+                        child
+                            .spawn()
+                            .insert_bundle(lyon().rectangle(width, height).outlined_pos(
+                                Color::GRAY,
+                                Color::BLACK,
+                                4.0,
+                                offset + vec2(-0.5 * width, 0.5 * height),
+                            ));
+                    });
+            }
+            Simple::UndergroundEntrance(pos, dir) => {
+                cmds.entity(entity)
+                    .insert(Name::new("UndergroundEntrance"))
+                    .insert(*pos)
+                    .insert(UndergroundBelt::new(UndergroundKind::Entrance, *dir))
+                    .insert(Belt {
+                        segments: vec![underground_entrance_segment(*pos, *dir)],
+                        lanes: Default::default(),
+                        output: None,
+                        output_lane: None,
+                        tier: BeltTier::Basic,
+                    })
+                    .insert(ItemInput::new(2))
+                    .insert(input(map_pos(0, 0), dir.opposite()))
+                    .insert_bundle(lyon().polygon(4, TILE_HALFSIZE).outlined(
+                        COLOR_SA2,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::Assembler(pos1, out_dir, recipe_name) => {
+                let recipe = recipes
+                    .as_deref()
+                    .and_then(|recipes| Some((recipes, recipes.lookup(recipe_name)?)));
+                let (recipes, recipe) = match recipe {
+                    Some(recipe) => recipe,
+                    None => {
+                        warn!("no recipe {} for assembler at {:?}", recipe_name, pos1);
+                        cmds.entity(entity).despawn();
+                        continue;
+                    }
+                };
+
+                let pos1 = *pos1;
+                let out_dir = *out_dir;
+                let in_dir = out_dir.opposite();
+                let right = map_pos(0, 0).step(out_dir.right());
+                let front = map_pos(0, 0).step(out_dir);
+                let offset = 0.5 * TILE_SIZE * (right + front).vec2();
+
+                let in1 = cmds
+                    .spawn()
+                    .insert(pos1)
+                    .insert(ItemInput::new(2))
+                    .insert(input(map_pos(0, 0), in_dir))
+                    .id();
+                let in2 = cmds
+                    .spawn()
+                    .insert(pos1 + right)
+                    .insert(ItemInput::new(2))
+                    .insert(input(right, in_dir))
+                    .id();
+
+                // fluids are piped in from the right side of the front right cell
+                let mut assembler = Assembler::new(recipe, recipes, vec![in1, in2]);
+                let fluid = items.as_deref().and_then(|items| {
+                    recipes
+                        .get(recipe)
+                        .inputs
+                        .iter()
+                        .map(|(item, _)| *item)
+                        .find(|item| items.get(*item).fluid)
+                });
+                if let Some(fluid) = fluid {
+                    let fluid_input = cmds
+                        .spawn()
+                        .insert(pos1 + front + right)
+                        .insert(FluidBox::new(PIPE_CAPACITY).with_filter(Some(fluid)))
+                        .insert(FluidConnections::new(&[(map_pos(0, 0), out_dir.right())]))
+                        .id();
+                    assembler.fluid_input = Some(fluid_input);
+                }
+
+                cmds.entity(entity)
+                    .insert(Name::new("Assembler"))
+                    .insert(pos1)
+                    .insert(Transform::default())
+                    .insert(GlobalTransform::default())
+                    .insert(assembler)
+                    .insert(outputs(&[(front, out_dir), (front + right, out_dir)]))
+                    .with_children(|child| {
+                        child.spawn().insert_bundle(
+                            lyon()
+                                .rectangle(2.0 * TILE_SIZE, 2.0 * TILE_SIZE)
+                                .outlined_pos(
+                                    COLOR_SA1,
+                                    Color::BLACK,
+                                    4.0,
+                                    offset + vec2(-TILE_SIZE, TILE_SIZE),
+                                ),
+                        );
+                    });
+            }
+            Simple::Chest(pos, in_dir, out_dir) => {
+                cmds.entity(entity)
+                    .insert(Name::new("Chest"))
+                    .insert(*pos)
+                    .insert(Chest::default())
+                    .insert(Inventory::new(CHEST_SLOTS))
+                    .insert(ItemInput::new(2))
+                    .insert(input(map_pos(0, 0), *in_dir))
+                    .insert(Transform::default())
+                    .insert(GlobalTransform::default())
+                    .with_children(|child| {
+                        child.spawn().insert_bundle(
+                            lyon().rectangle(TILE_SIZE, TILE_SIZE).outlined_pos(
+                                COLOR_SA4,
+                                Color::BLACK,
+                                4.0,
+                                vec2(-TILE_HALFSIZE, TILE_HALFSIZE),
+                            ),
+                        );
+                    });
+                if let Some(out_dir) = out_dir {
+                    cmds.entity(entity).insert(output((0, 0), *out_dir));
+                }
+            }
+            Simple::Inserter(pos, pickup, drop, filter) => {
+                let filter = match lookup_items(items.as_deref(), filter.as_deref()) {
+                    Ok(filter) => filter,
+                    Err(name) => {
+                        warn!("no item {} for inserter at {:?}", name, pos);
+                        cmds.entity(entity).despawn();
+                        continue;
+                    }
+                };
+                cmds.entity(entity)
+                    .insert(Name::new("Inserter"))
+                    .insert(*pos)
+                    .insert(Inserter::new(*pickup, *drop, filter))
+                    .insert_bundle(lyon().polygon(3, TILE_HALFSIZE).outlined(
+                        COLOR_SA1,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::MiningDrill(pos, out_dir) => {
+                let right = map_pos(0, 0).step(out_dir.right());
+                let front = map_pos(0, 0).step(*out_dir);
+                let offset = 0.5 * TILE_SIZE * (right + front).vec2();
+
+                cmds.entity(entity)
+                    .insert(Name::new("MiningDrill"))
+                    .insert(*pos)
+                    .insert(MiningDrill::new(vec![
+                        map_pos(0, 0),
+                        right,
+                        front,
+                        front + right,
+                    ]))
+                    .insert(output(front, *out_dir))
+                    .insert(Transform::default())
+                    .insert(GlobalTransform::default())
+                    .with_children(|child| {
+                        child.spawn().insert_bundle(
+                            lyon()
+                                .rectangle(2.0 * TILE_SIZE, 2.0 * TILE_SIZE)
+                                .outlined_pos(
+                                    COLOR_SA3,
+                                    Color::BLACK,
+                                    4.0,
+                                    offset + vec2(-TILE_SIZE, TILE_SIZE),
+                                ),
+                        );
+                    });
+            }
+            Simple::PowerPlant(pos, watts) => {
+                cmds.entity(entity)
+                    .insert(Name::new("PowerPlant"))
+                    .insert(*pos)
+                    .insert(PowerProducer { watts: *watts })
+                    .insert_bundle(lyon().polygon(8, TILE_HALFSIZE).outlined(
+                        COLOR_SB2,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::PowerPole(pos) => {
+                cmds.entity(entity)
+                    .insert(Name::new("PowerPole"))
+                    .insert(*pos)
+                    .insert(PowerPole::default())
+                    .insert_bundle(lyon().circle(0.25 * TILE_SIZE).outlined(
+                        COLOR_SB1,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::Powered(watts, simple) => {
+                // the building is spawned next frame as if it was a plain one
+                cmds.entity(entity)
+                    .insert(PowerConsumer::new(*watts))
+                    .insert((**simple).clone());
+            }
+            Simple::Pipe(pos) => {
+                cmds.entity(entity)
+                    .insert(Name::new("Pipe"))
+                    .insert(*pos)
+                    .insert(Pipe)
+                    .insert(FluidBox::new(PIPE_CAPACITY))
+                    .insert(FluidConnections::all_sides())
+                    .insert_bundle(lyon().polygon(4, 0.5 * TILE_HALFSIZE).outlined(
+                        COLOR_SA0,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::Tank(pos) => {
+                cmds.entity(entity)
+                    .insert(Name::new("Tank"))
+                    .insert(*pos)
+                    .insert(Tank)
+                    .insert(FluidBox::new(TANK_CAPACITY))
+                    .insert(FluidConnections::all_sides())
+                    .insert_bundle(lyon().circle(TILE_HALFSIZE).outlined(
+                        COLOR_SA0,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::Pump(pos, out_dir, fluid_name) => {
+                let fluid = match lookup_items(items.as_deref(), Some(fluid_name.as_str())) {
+                    Ok(Some(fluid)) => fluid,
+                    _ => {
+                        warn!("no fluid {} for pump at {:?}", fluid_name, pos);
+                        cmds.entity(entity).despawn();
+                        continue;
+                    }
+                };
+                cmds.entity(entity)
+                    .insert(Name::new("Pump"))
+                    .insert(*pos)
+                    .insert(Pump {
+                        fluid,
+                        rate: PUMP_RATE,
+                    })
+                    .insert(FluidBox::new(PIPE_CAPACITY))
+                    .insert(FluidConnections::new(&[(map_pos(0, 0), *out_dir)]))
+                    .insert_bundle(lyon().polygon(5, TILE_HALFSIZE).outlined(
+                        COLOR_SA2,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
+            Simple::UndergroundExit(pos, dir) => {
+                cmds.entity(entity)
+                    .insert(Name::new("UndergroundExit"))
+                    .insert(*pos)
+                    .insert(UndergroundBelt::new(UndergroundKind::Exit, *dir))
+                    .insert_bundle(lyon().polygon(4, TILE_HALFSIZE).outlined(
+                        COLOR_SA3,
+                        Color::BLACK,
+                        4.0,
+                    ));
+            }
         }
     }
 }
+
+/// the item of that name if there is one, the name if it is not in the registry
+fn lookup_items<'a>(
+    items: Option<&ItemRegistry>,
+    name: Option<&'a str>,
+) -> Result<Option<ItemId>, &'a str> {
+    match name {
+        Some(name) => items
+            .and_then(|items| items.lookup(name))
+            .map(Some)
+            .ok_or(name),
+        None => Ok(None),
+    }
+}
+
+fn spawn_belt(
+    cmds: &mut Commands,
+    entity: Entity,
+    belt_atlas: Option<&BeltAtlasHandle>,
+    pos: MapPos,
+    in_dir: CompassDir,
+    out_dir: CompassDir,
+    tier: BeltTier,
+) {
+    let pos_vec = pos.vec2();
+    let in_vec = 0.5 * in_dir.vec2();
+    let out_vec = 0.5 * out_dir.vec2();
+    let start = TILE_SIZE * vec3(pos_vec.x + in_vec.x, pos_vec.y + in_vec.y, 0.0);
+    let end = TILE_SIZE * vec3(pos_vec.x + out_vec.x, pos_vec.y + out_vec.y, 0.0);
+    let segment = BeltSegment { start, end };
+
+    // only straight belts can be loaded from the side
+    let belt_input = if in_dir == out_dir.opposite() {
+        input(map_pos(0, 0), in_dir).with_side_loading()
+    } else {
+        input(map_pos(0, 0), in_dir)
+    };
+
+    cmds.entity(entity)
+        .insert(Name::new("Belt"))
+        .insert(pos)
+        .insert(Belt {
+            segments: vec![segment],
+            lanes: Default::default(),
+            output: None,
+            output_lane: None,
+            tier,
+        })
+        .insert(ItemInput::new(2))
+        .insert(belt_input)
+        .insert(output((0, 0), out_dir));
+
+    if let Some(belt_atlas) = belt_atlas {
+        cmds.entity(entity).insert_bundle(SpriteSheetBundle {
+            sprite: TextureAtlasSprite::new(0),
+            texture_atlas: (*belt_atlas).clone(),
+            ..Default::default()
+        });
+    }
+}
@@ -0,0 +1,246 @@
+use bevy::{ecs::schedule::ShouldRun, prelude::*};
+
+use crate::{AppState, LoadingWorld, SimRng};
+
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const TICK_SECONDS: f32 = 1.0 / TICKS_PER_SECOND as f32;
+/// frames that took too long do not try to catch up more than this
+pub const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// Runs after `CoreStage::Update` a whole number of times per frame.
+/// Systems in here must only use `SimulationTime` and never `Time`,
+/// so two runs of the same layout give identical results.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+pub struct SimulationStage;
+
+#[derive(Default, Debug)]
+pub struct SimulationTime {
+    tick: u64,
+    accumulator: f64,
+    ticks_this_frame: u32,
+    /// run this many ticks every frame regardless of `Time`, used headless
+    ticks_per_update: Option<u32>,
+}
+
+impl SimulationTime {
+    pub fn stepped(ticks_per_update: u32) -> Self {
+        Self {
+            ticks_per_update: Some(ticks_per_update),
+            ..Default::default()
+        }
+    }
+
+    /// number of the tick that is currently simulated, starts with 1
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.tick as f64 * TICK_SECONDS as f64
+    }
+
+    /// continues counting from a saved world
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick = tick;
+    }
+
+    /// the tick that lies `seconds` after the current tick
+    pub fn tick_after(&self, seconds: f32) -> u64 {
+        self.tick + ticks_from_seconds(seconds)
+    }
+}
+
+pub fn ticks_from_seconds(seconds: f32) -> u64 {
+    (seconds * TICKS_PER_SECOND as f32).round() as u64
+}
+
+pub struct SimulationPlugin;
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        if app.world().get_resource::<SimRng>().is_none() {
+            app.insert_resource(SimRng::from_args_or_config(None));
+        }
+
+        if app.world().get_resource::<SimulationTime>().is_none() {
+            app.insert_resource(SimulationTime::default());
+        }
+
+        app.add_stage_after(
+            CoreStage::Update,
+            SimulationStage,
+            SystemStage::parallel().with_run_criteria(simulation_tick_run_criteria.system()),
+        );
+    }
+}
+
+/// Accumulates frame time and lets the simulation stage run once per full tick.
+/// Without an `AppState` (headless) the simulation always runs, otherwise only
+/// while the game is running. It waits while a saved world is loaded.
+fn simulation_tick_run_criteria(
+    time: Res<Time>,
+    state: Option<Res<State<AppState>>>,
+    loading: Option<Res<LoadingWorld>>,
+    mut sim_time: ResMut<SimulationTime>,
+    mut looping: Local<bool>,
+) -> ShouldRun {
+    let running = state.map_or(true, |state| *state.current() == AppState::GameRunning);
+    if !running || loading.is_some() {
+        sim_time.accumulator = 0.0;
+        return ShouldRun::No;
+    }
+
+    if !*looping {
+        sim_time.accumulator += match sim_time.ticks_per_update {
+            Some(ticks) => ticks as f64 * TICK_SECONDS as f64,
+            None => time.delta_seconds_f64(),
+        };
+        sim_time.ticks_this_frame = 0;
+    }
+
+    if sim_time.accumulator >= TICK_SECONDS as f64 {
+        let max_ticks = sim_time.ticks_per_update.unwrap_or(MAX_TICKS_PER_FRAME);
+        if sim_time.ticks_this_frame >= max_ticks {
+            sim_time.accumulator = 0.0;
+            *looping = false;
+            return ShouldRun::No;
+        }
+        sim_time.accumulator -= TICK_SECONDS as f64;
+        sim_time.ticks_this_frame += 1;
+        sim_time.tick += 1;
+        *looping = true;
+        ShouldRun::YesAndCheckAgain
+    } else {
+        *looping = false;
+        ShouldRun::No
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::*;
+
+    fn sim_app(time: SimulationTime) -> App {
+        let mut app = App::build();
+        app.insert_resource(time)
+            .insert_resource(SimRng::new(42))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(SimulationPlugin);
+        app.app
+    }
+
+    fn tick(app: &App) -> u64 {
+        app.world.get_resource::<SimulationTime>().unwrap().tick()
+    }
+
+    /// every belt by its pos with the bits of its item positions
+    fn run_layout(
+        layout: &str,
+        seed: u64,
+        updates: usize,
+    ) -> Vec<(i32, i32, Vec<(u32, u16, usize)>)> {
+        let path = format!("{}/layouts/{}", env!("CARGO_MANIFEST_DIR"), layout);
+        let file = std::fs::File::open(path).expect("layout");
+        let simples: Vec<Simple> = ron::de::from_reader(file).expect("layout");
+
+        let mut app = App::build();
+        app.insert_resource(SimulationTime::stepped(3))
+            .insert_resource(SimRng::new(seed))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(SimulationPlugin)
+            .add_plugin(MapPlugin)
+            .add_plugin(SimpleSpawnerPlugin)
+            .add_plugin(BeltInputOutputHookupPlugin)
+            .add_plugin(BeltPlugin);
+        let mut app = app.app;
+        for simple in simples {
+            app.world.spawn().insert(simple);
+        }
+        for _ in 0..updates {
+            app.update();
+        }
+
+        let mut belts: Vec<_> = app
+            .world
+            .query::<(&MapPos, &Belt)>()
+            .iter(&app.world)
+            .map(|(pos, belt)| {
+                let items = belt
+                    .items()
+                    .map(|it| (it.pos.to_bits(), it.item.0, it.lane.index()))
+                    .collect();
+                (pos.x, pos.y, items)
+            })
+            .collect();
+        belts.sort();
+        belts
+    }
+
+    #[test]
+    fn same_seed_same_items() {
+        let first = run_layout("merger.ron", 7, 200);
+        assert!(first.iter().any(|(_, _, items)| !items.is_empty()));
+        assert_eq!(first, run_layout("merger.ron", 7, 200));
+    }
+
+    #[test]
+    fn stepped_runs_its_ticks_every_update() {
+        let mut app = sim_app(SimulationTime::stepped(3));
+        app.update();
+        app.update();
+        assert_eq!(6, tick(&app));
+    }
+
+    #[test]
+    fn a_long_frame_runs_at_most_max_ticks() {
+        let mut app = sim_app(SimulationTime::default());
+        // the first frame has no delta
+        app.update();
+        std::thread::sleep(Duration::from_secs_f32(
+            2.0 * MAX_TICKS_PER_FRAME as f32 * TICK_SECONDS,
+        ));
+        app.update();
+        assert_eq!(MAX_TICKS_PER_FRAME as u64, tick(&app));
+    }
+
+    #[test]
+    fn paused_outside_game_running() {
+        let mut app = App::build();
+        app.insert_resource(SimulationTime::stepped(1))
+            .insert_resource(SimRng::new(42))
+            .add_plugins(MinimalPlugins)
+            .add_state(AppState::GamePaused)
+            .add_plugin(SimulationPlugin);
+        let mut app = app.app;
+        app.update();
+        app.update();
+        assert_eq!(0, tick(&app));
+
+        app.world
+            .get_resource_mut::<State<AppState>>()
+            .unwrap()
+            .set(AppState::GameRunning)
+            .unwrap();
+        app.update();
+        app.update();
+        assert!(tick(&app) > 0);
+    }
+
+    #[test]
+    fn paused_while_a_world_loads() {
+        let mut app = sim_app(SimulationTime::stepped(1));
+        app.update();
+        assert_eq!(1, tick(&app));
+
+        app.world.insert_resource(LoadingWorld);
+        app.update();
+        app.update();
+        assert_eq!(1, tick(&app));
+
+        app.world.remove_resource::<LoadingWorld>();
+        app.update();
+        assert_eq!(2, tick(&app));
+    }
+}
@@ -0,0 +1,329 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{Belt, ItemId, ItemInput, ItemRegistry, Lane, SimulationTime};
+
+/// Which side of a splitter is meant is seen in output direction,
+/// `Lane::Left` is the first cell and `Lane::Right` the cell right of it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitterSettings {
+    /// take items from this input first, otherwise alternate
+    pub input_priority: Option<Lane>,
+    /// put items on this output first, otherwise alternate
+    pub output_priority: Option<Lane>,
+    /// items matching the filter only go to the output priority side, left if none,
+    /// all other items only go to the other side, the item is given by name
+    pub filter: Option<String>,
+}
+
+pub struct Splitter {
+    pub inputs: [Entity; 2],
+    pub outputs: [Option<Entity>; 2],
+    pub settings: SplitterSettings,
+    /// the item of `settings.filter`
+    pub filter: Option<ItemId>,
+
+    /// simulation tick
+    pub next_time: u64,
+    pub cooldown: f32,
+    pub input_cursor: usize,
+    pub output_cursor: usize,
+}
+
+impl Splitter {
+    pub fn new(inputs: [Entity; 2], settings: SplitterSettings, filter: Option<ItemId>) -> Self {
+        Self {
+            inputs,
+            outputs: [None, None],
+            settings,
+            filter,
+            next_time: 0,
+            cooldown: 0.0,
+            input_cursor: 0,
+            output_cursor: 0,
+        }
+    }
+
+    fn input_order(&self) -> [usize; 2] {
+        side_order(self.settings.input_priority, self.input_cursor)
+    }
+
+    fn output_order(&self, item: ItemId) -> Vec<usize> {
+        if let Some(filter) = self.filter {
+            let filter_side = self.settings.output_priority.unwrap_or(Lane::Left).index();
+            if item == filter {
+                vec![filter_side]
+            } else {
+                vec![1 - filter_side]
+            }
+        } else {
+            side_order(self.settings.output_priority, self.output_cursor).to_vec()
+        }
+    }
+}
+
+fn side_order(priority: Option<Lane>, cursor: usize) -> [usize; 2] {
+    let first = priority.map_or(cursor, |lane| lane.index());
+    [first, 1 - first]
+}
+
+pub fn splitter_system(
+    mut splitters: Query<&mut Splitter>,
+    mut inputs: Query<&mut ItemInput>,
+    mut belts: Query<&mut Belt>,
+    sim_time: Res<SimulationTime>,
+    registry: Res<ItemRegistry>,
+) {
+    let tick = sim_time.tick();
+
+    for mut splitter in splitters.iter_mut() {
+        if splitter.next_time > tick {
+            continue;
+        }
+
+        let mut did_something = false;
+
+        // every input can pass on one item per step
+        for in_index in splitter.input_order().iter().copied() {
+            let mut input = match inputs.get_mut(splitter.inputs[in_index]) {
+                Ok(input) => input,
+                Err(_) => continue,
+            };
+            let item = match input.oldest_item() {
+                Some(item) => item.clone(),
+                None => continue,
+            };
+
+            for out_index in splitter.output_order(item.item) {
+                let output_e = match splitter.outputs[out_index] {
+                    Some(output_e) => output_e,
+                    None => continue,
+                };
+
+                if let Ok(mut belt) = belts.get_mut(output_e) {
+                    if belt.is_space(&item, &registry) {
+                        if let Some(item) = input.pop_oldest_item() {
+                            belt.add_item(item);
+                            splitter.input_cursor = 1 - in_index;
+                            splitter.output_cursor = 1 - out_index;
+                            did_something = true;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        if did_something {
+            splitter.next_time = sim_time.tick_after(splitter.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec3;
+
+    use super::*;
+    use crate::{BeltItem, BeltSegment, BeltTier};
+
+    const RED: ItemId = ItemId(0);
+    const GREEN: ItemId = ItemId(1);
+
+    /// a splitter between two inputs and two empty belts one tile long
+    struct TestSplitter {
+        world: World,
+        inputs: [Entity; 2],
+        outputs: [Entity; 2],
+    }
+
+    impl TestSplitter {
+        fn new(settings: SplitterSettings, filter: Option<ItemId>) -> Self {
+            let mut world = World::default();
+            world.insert_resource(SimulationTime::default());
+            world.insert_resource(ItemRegistry::default());
+
+            let mut input = || world.spawn().insert(ItemInput::new(2)).id();
+            let inputs = [input(), input()];
+            let mut output = || {
+                world
+                    .spawn()
+                    .insert(Belt {
+                        segments: vec![BeltSegment {
+                            start: Vec3::ZERO,
+                            end: vec3(48.0, 0.0, 0.0),
+                        }],
+                        lanes: Default::default(),
+                        output: None,
+                        output_lane: None,
+                        tier: BeltTier::Basic,
+                    })
+                    .id()
+            };
+            let outputs = [output(), output()];
+
+            let mut splitter = Splitter::new(inputs, settings, filter);
+            splitter.outputs = [Some(outputs[0]), Some(outputs[1])];
+            world.spawn().insert(splitter);
+            Self {
+                world,
+                inputs,
+                outputs,
+            }
+        }
+
+        fn put(&mut self, side: usize, item: ItemId) {
+            let mut input = self.world.get_mut::<ItemInput>(self.inputs[side]).unwrap();
+            input.add_items(Some(BeltItem::new(0.0, item)));
+        }
+
+        /// an item at the start of the output belt, nothing fits behind it
+        fn block(&mut self, side: usize) {
+            let mut belt = self.world.get_mut::<Belt>(self.outputs[side]).unwrap();
+            belt.add_item(BeltItem::new(0.0, RED));
+        }
+
+        fn step(&mut self) {
+            let mut stage = SystemStage::single(splitter_system.system());
+            stage.run(&mut self.world);
+        }
+
+        /// the items which came out on `side`, they are taken off its belt
+        fn out(&mut self, side: usize) -> Vec<ItemId> {
+            let mut belt = self.world.get_mut::<Belt>(self.outputs[side]).unwrap();
+            belt.take_items().into_iter().map(|it| it.item).collect()
+        }
+
+        fn waiting(&self, side: usize) -> usize {
+            let input = self.world.get::<ItemInput>(self.inputs[side]).unwrap();
+            input.items().len()
+        }
+    }
+
+    #[test]
+    fn input_priority_goes_first() {
+        let mut splitter = TestSplitter::new(
+            SplitterSettings {
+                input_priority: Some(Lane::Right),
+                ..Default::default()
+            },
+            None,
+        );
+        splitter.block(1);
+        splitter.put(0, RED);
+        splitter.put(1, GREEN);
+        splitter.step();
+        assert_eq!(vec![GREEN], splitter.out(0));
+        assert_eq!(1, splitter.waiting(0));
+
+        splitter.put(1, GREEN);
+        splitter.step();
+        assert_eq!(vec![GREEN], splitter.out(0));
+        assert_eq!(1, splitter.waiting(0));
+    }
+
+    #[test]
+    fn output_priority_goes_first() {
+        let mut splitter = TestSplitter::new(
+            SplitterSettings {
+                output_priority: Some(Lane::Right),
+                ..Default::default()
+            },
+            None,
+        );
+        for _ in 0..2 {
+            splitter.put(0, RED);
+            splitter.step();
+            assert!(splitter.out(0).is_empty());
+            assert_eq!(vec![RED], splitter.out(1));
+        }
+
+        // the other side only when the priority side is full
+        splitter.block(1);
+        splitter.put(0, GREEN);
+        splitter.step();
+        assert_eq!(vec![GREEN], splitter.out(0));
+    }
+
+    #[test]
+    fn without_priority_sides_alternate() {
+        let mut splitter = TestSplitter::new(SplitterSettings::default(), None);
+        for side in [0, 1, 0, 1].iter().copied() {
+            splitter.put(0, RED);
+            splitter.step();
+            assert_eq!(vec![RED], splitter.out(side));
+            assert!(splitter.out(1 - side).is_empty());
+        }
+
+        // the input which had to wait goes first next time
+        splitter.block(1);
+        splitter.put(0, RED);
+        splitter.put(1, GREEN);
+        splitter.step();
+        let first = splitter.out(0);
+        splitter.put(0, RED);
+        splitter.put(1, GREEN);
+        splitter.step();
+        let second = splitter.out(0);
+        assert_eq!(1, first.len());
+        assert_eq!(1, second.len());
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn filter_sends_matching_items_to_the_priority_side() {
+        let settings = |output_priority| SplitterSettings {
+            output_priority,
+            filter: Some("green".to_owned()),
+            ..Default::default()
+        };
+        for (priority, side) in [(None, 0), (Some(Lane::Left), 0), (Some(Lane::Right), 1)]
+            .iter()
+            .copied()
+        {
+            let mut splitter = TestSplitter::new(settings(priority), Some(GREEN));
+            for _ in 0..2 {
+                splitter.put(0, GREEN);
+                splitter.step();
+                assert_eq!(vec![GREEN], splitter.out(side));
+                assert!(splitter.out(1 - side).is_empty());
+
+                splitter.put(0, RED);
+                splitter.step();
+                assert_eq!(vec![RED], splitter.out(1 - side));
+                assert!(splitter.out(side).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn filtered_items_wait_for_their_side() {
+        let mut splitter = TestSplitter::new(
+            SplitterSettings {
+                filter: Some("green".to_owned()),
+                ..Default::default()
+            },
+            Some(GREEN),
+        );
+        splitter.block(1);
+        splitter.put(0, RED);
+        splitter.step();
+        assert_eq!(1, splitter.waiting(0));
+        assert!(splitter.out(0).is_empty());
+
+        splitter.block(0);
+        splitter.put(1, GREEN);
+        splitter.step();
+        assert_eq!(1, splitter.waiting(1));
+        assert_eq!(vec![RED], splitter.out(0));
+
+        // once there is space they go
+        splitter.step();
+        assert_eq!(vec![GREEN], splitter.out(0));
+        assert_eq!(0, splitter.waiting(1));
+        assert_eq!(vec![RED], splitter.out(1));
+        splitter.step();
+        assert_eq!(vec![RED], splitter.out(1));
+        assert_eq!(0, splitter.waiting(0));
+    }
+}
@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+
+use crate::{
+    output, Belt, BeltLine, BeltSegment, CompassDir, FusedInto, MapCache, MapLayer, MapPos,
+    MultipleOutputs, TILE_SIZE,
+};
+
+/// how many cells an underground belt can pass under at most
+pub const UNDERGROUND_BELT_MAX_GAP: i32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UndergroundKind {
+    Entrance,
+    Exit,
+}
+
+/// An entrance is a `Belt` with a `SingleInput`, when it finds its exit its segment
+/// spans the whole tunnel and it outputs at the exit.
+/// An exit is just a marker on the map for the entrance to find.
+pub struct UndergroundBelt {
+    pub kind: UndergroundKind,
+    /// direction of travel
+    pub dir: CompassDir,
+    pub partner: Option<Entity>,
+}
+
+impl UndergroundBelt {
+    pub fn new(kind: UndergroundKind, dir: CompassDir) -> Self {
+        Self {
+            kind,
+            dir,
+            partner: None,
+        }
+    }
+}
+
+/// from the input side of an unpaired entrance to its center
+pub fn underground_entrance_segment(entrance: MapPos, dir: CompassDir) -> BeltSegment {
+    let start = TILE_SIZE * (entrance.vec2() + 0.5 * dir.opposite().vec2());
+    let end = TILE_SIZE * entrance.vec2();
+    BeltSegment {
+        start: start.extend(0.0),
+        end: end.extend(0.0),
+    }
+}
+
+/// from the input side of the entrance to the output side of the exit
+pub fn underground_belt_segment(entrance: MapPos, exit: MapPos, dir: CompassDir) -> BeltSegment {
+    let start = TILE_SIZE * (entrance.vec2() + 0.5 * dir.opposite().vec2());
+    let end = TILE_SIZE * (exit.vec2() + 0.5 * dir.vec2());
+    BeltSegment {
+        start: start.extend(0.0),
+        end: end.extend(0.0),
+    }
+}
+
+pub fn underground_belt_pairing_system(
+    mut cmds: Commands,
+    map: Res<MapCache>,
+    mut undergrounds: Query<(Entity, &MapPos, &mut UndergroundBelt)>,
+    mut belts: Query<&mut Belt>,
+    fused: Query<(), Or<(With<BeltLine>, With<FusedInto>)>>,
+) {
+    // the survivor of a removed pair is reset to how it was spawned, so it can pair again
+    let paired: Vec<(Entity, Entity)> = undergrounds
+        .iter_mut()
+        .filter_map(|(entity, _, it)| Some((entity, it.partner?)))
+        .collect();
+    let orphans: Vec<Entity> = paired
+        .into_iter()
+        .filter(|(_, partner)| {
+            undergrounds
+                .get_component::<UndergroundBelt>(*partner)
+                .is_err()
+        })
+        .map(|(entity, _)| entity)
+        .collect();
+    for orphan in orphans {
+        if let Ok((_, pos, mut it)) = undergrounds.get_mut(orphan) {
+            if it.kind == UndergroundKind::Entrance {
+                cmds.entity(orphan).remove::<MultipleOutputs>();
+                // its line is split first, the tunnel is cut back once it has its own items
+                if fused.get(orphan).is_ok() {
+                    continue;
+                }
+                if let Ok(mut belt) = belts.get_mut(orphan) {
+                    let items = belt.take_items();
+                    belt.segments = vec![underground_entrance_segment(*pos, it.dir)];
+                    belt.output = None;
+                    belt.output_lane = None;
+                    // items in the tunnel are lost with it
+                    let length = belt.total_length();
+                    for item in items.into_iter().filter(|item| item.pos <= length) {
+                        belt.add_item(item);
+                    }
+                }
+            }
+            it.partner = None;
+            debug!("underground {:?} lost its partner", orphan);
+        }
+    }
+
+    let entrances: Vec<(Entity, MapPos, CompassDir)> = undergrounds
+        .iter_mut()
+        .filter(|(_, _, it)| it.kind == UndergroundKind::Entrance && it.partner.is_none())
+        .map(|(entity, pos, it)| (entity, *pos, it.dir))
+        .collect();
+
+    for (entrance, pos, dir) in entrances {
+        // walk under at most max gap cells and take the first free exit in the same direction
+        let exit = std::iter::successors(Some(pos.step(dir)), |it| Some(it.step(dir)))
+            .take(UNDERGROUND_BELT_MAX_GAP as usize + 1)
+            .find_map(|exit_pos| {
+                let exit = map.at(MapLayer::Belts, &exit_pos)?;
+                let it = undergrounds.get_component::<UndergroundBelt>(exit).ok()?;
+                if it.kind == UndergroundKind::Exit && it.dir == dir && it.partner.is_none() {
+                    Some((exit, exit_pos))
+                } else {
+                    None
+                }
+            });
+
+        if let Some((exit, exit_pos)) = exit {
+            if let Ok(mut it) = undergrounds.get_component_mut::<UndergroundBelt>(entrance) {
+                it.partner = Some(exit);
+            }
+            if let Ok(mut it) = undergrounds.get_component_mut::<UndergroundBelt>(exit) {
+                it.partner = Some(entrance);
+            }
+            if let Ok(mut belt) = belts.get_mut(entrance) {
+                belt.segments = vec![underground_belt_segment(pos, exit_pos, dir)];
+            }
+
+            let offset = MapPos::new(exit_pos.x - pos.x, exit_pos.y - pos.y);
+            cmds.entity(entrance).insert(output(offset, dir));
+
+            debug!("underground {:?} paired with {:?}", entrance, exit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    fn test_app(simples: Vec<Simple>) -> App {
+        let mut app = App::build();
+        app.insert_resource(SimulationTime::stepped(1))
+            .insert_resource(SimRng::new(42))
+            .add_plugins(MinimalPlugins)
+            .add_plugin(SimulationPlugin)
+            .add_plugin(MapPlugin)
+            .add_plugin(SimpleSpawnerPlugin)
+            .add_plugin(BeltInputOutputHookupPlugin)
+            .add_plugin(BeltPlugin);
+        let mut app = app.app;
+        for simple in simples {
+            app.world.spawn().insert(simple);
+        }
+        for _ in 0..3 {
+            app.update();
+        }
+        app
+    }
+
+    fn underground_at(world: &mut World, x: i32, y: i32) -> Entity {
+        world
+            .query_filtered::<(Entity, &MapPos), With<UndergroundBelt>>()
+            .iter(world)
+            .find(|(_, pos)| **pos == map_pos(x, y))
+            .map(|(entity, _)| entity)
+            .expect("underground")
+    }
+
+    fn partner(world: &World, entity: Entity) -> Option<Entity> {
+        world.get::<UndergroundBelt>(entity).unwrap().partner
+    }
+
+    #[test]
+    fn pairs_with_an_exit_up_to_max_gap_away() {
+        use CompassDir::*;
+        let far = UNDERGROUND_BELT_MAX_GAP + 1;
+        let mut app = test_app(vec![
+            Simple::UndergroundEntrance(map_pos(0, 0), E),
+            Simple::UndergroundExit(map_pos(far, 0), E),
+            Simple::UndergroundEntrance(map_pos(0, 2), E),
+            Simple::UndergroundExit(map_pos(far + 1, 2), E),
+        ]);
+        let world = &mut app.world;
+
+        let (entrance, exit) = (underground_at(world, 0, 0), underground_at(world, far, 0));
+        assert_eq!(Some(exit), partner(world, entrance));
+        assert_eq!(Some(entrance), partner(world, exit));
+        let segment = &world.get::<Belt>(entrance).unwrap().segments[0];
+        let expected = underground_belt_segment(map_pos(0, 0), map_pos(far, 0), E);
+        assert_eq!((expected.start, expected.end), (segment.start, segment.end));
+
+        let (entrance, exit) = (
+            underground_at(world, 0, 2),
+            underground_at(world, far + 1, 2),
+        );
+        assert_eq!(None, partner(world, entrance));
+        assert_eq!(None, partner(world, exit));
+    }
+
+    #[test]
+    fn skips_exits_of_the_wrong_direction_or_with_a_partner() {
+        use CompassDir::*;
+        let mut app = test_app(vec![
+            Simple::UndergroundEntrance(map_pos(0, 0), E),
+            Simple::UndergroundExit(map_pos(1, 0), W),
+            Simple::UndergroundExit(map_pos(2, 0), E),
+            Simple::UndergroundEntrance(map_pos(0, 2), E),
+            Simple::UndergroundEntrance(map_pos(1, 2), E),
+            Simple::UndergroundExit(map_pos(2, 2), E),
+            Simple::UndergroundExit(map_pos(3, 2), E),
+        ]);
+        let world = &mut app.world;
+
+        let entrance = underground_at(world, 0, 0);
+        let wrong_dir = underground_at(world, 1, 0);
+        assert_eq!(Some(underground_at(world, 2, 0)), partner(world, entrance));
+        assert_eq!(None, partner(world, wrong_dir));
+
+        // whichever pairs first, the other one passes under its exit
+        let entrances = [underground_at(world, 0, 2), underground_at(world, 1, 2)];
+        let exits = [underground_at(world, 2, 2), underground_at(world, 3, 2)];
+        for exit in exits.iter() {
+            let entrance = partner(world, *exit).expect("paired exit");
+            assert!(entrances.contains(&entrance));
+            assert_eq!(Some(*exit), partner(world, entrance));
+        }
+        assert_ne!(partner(world, exits[0]), partner(world, exits[1]));
+    }
+
+    #[test]
+    fn an_entrance_without_exit_is_reset_and_pairs_again() {
+        use CompassDir::*;
+        let mut app = test_app(vec![
+            Simple::ItemGenerator(map_pos(-1, 0), E),
+            Simple::UndergroundEntrance(map_pos(0, 0), E),
+            Simple::UndergroundExit(map_pos(3, 0), E),
+        ]);
+        // nothing takes items at the exit, they pile up in the tunnel
+        for _ in 0..600 {
+            app.update();
+        }
+        let world = &mut app.world;
+        let entrance = underground_at(world, 0, 0);
+        let exit = underground_at(world, 3, 0);
+        let entrance_segment = underground_entrance_segment(map_pos(0, 0), E);
+        let entrance_length = entrance_segment.start.distance(entrance_segment.end);
+        let before: Vec<BeltItem> = world.get::<Belt>(entrance).unwrap().items().collect();
+        assert!(before.iter().any(|it| it.pos > entrance_length));
+
+        despawn_building(world, exit);
+        app.update();
+        let world = &mut app.world;
+        assert_eq!(None, partner(world, entrance));
+        assert!(world.get::<MultipleOutputs>(entrance).is_none());
+        let belt = world.get::<Belt>(entrance).unwrap();
+        assert_eq!(None, belt.output());
+        assert_eq!(1, belt.segments.len());
+        assert_eq!(
+            (entrance_segment.start, entrance_segment.end),
+            (belt.segments[0].start, belt.segments[0].end)
+        );
+        // the items in the tunnel are gone, the ones before it stay
+        let after: Vec<BeltItem> = belt.items().collect();
+        assert!(after.len() < before.len());
+        assert!(after.iter().all(|it| it.pos <= entrance_length));
+
+        world
+            .spawn()
+            .insert(Simple::UndergroundExit(map_pos(2, 0), E));
+        for _ in 0..3 {
+            app.update();
+        }
+        let world = &mut app.world;
+        let exit = underground_at(world, 2, 0);
+        assert_eq!(Some(exit), partner(world, entrance));
+        assert_eq!(Some(entrance), partner(world, exit));
+    }
+}
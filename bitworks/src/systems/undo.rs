@@ -0,0 +1,508 @@
+use std::marker::PhantomData;
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{despawn_building, BuildingSave, MapCache, MapPos, Restore, Simple};
+
+/// Ctrl+Z undoes and Ctrl+Y redoes the edits of an `UndoStack<E>`,
+/// the app applies them with `apply_pending` in a system after the label "undo_keys".
+pub struct UndoPlugin<E>(PhantomData<E>);
+
+impl<E> Default for UndoPlugin<E> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<E: Edit> Plugin for UndoPlugin<E> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.insert_resource(UndoStack::<E>::default())
+            .add_system(undo_keys_system::<E>.system().label("undo_keys"));
+    }
+}
+
+/// A placement or removal which records enough to be inverted.
+pub trait Edit: Clone + Send + Sync + 'static {
+    fn inverse(&self) -> Self;
+
+    /// `queued` was applied as `applied`, if that placed something again under a new id
+    /// this edit is changed to refer to the new one
+    fn remap(&mut self, _queued: &Self, _applied: &Self) {}
+}
+
+/// an edit waiting for the app, undo and redo take their step once it is their turn
+enum Queued<E> {
+    /// a new edit and the group it was pushed in
+    New(E, Option<u32>),
+    Undo,
+    Redo,
+}
+
+/// Every edit goes through here, it is queued to be applied and recorded once the app applied it.
+/// Edits between `begin_group` and `end_group`, like those of a drag, are one step.
+pub struct UndoStack<E> {
+    done: Vec<Vec<E>>,
+    undone: Vec<Vec<E>>,
+    /// the open group, counted up by `begin_group`
+    group: Option<u32>,
+    groups: u32,
+    /// the group the last step of `done` belongs to
+    last_group: Option<u32>,
+    /// edits the app still has to apply
+    pending: Vec<Queued<E>>,
+}
+
+impl<E> Default for UndoStack<E> {
+    fn default() -> Self {
+        Self {
+            done: Vec::new(),
+            undone: Vec::new(),
+            group: None,
+            groups: 0,
+            last_group: None,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<E: Edit> UndoStack<E> {
+    /// queues `edit`, once it is applied nothing can be redone
+    pub fn push(&mut self, edit: E) {
+        self.pending.push(Queued::New(edit, self.group));
+    }
+
+    pub fn begin_group(&mut self) {
+        self.groups += 1;
+        self.group = Some(self.groups);
+    }
+
+    pub fn end_group(&mut self) {
+        self.group = None;
+    }
+
+    /// queues the inverse of the last step, not while a group is open
+    pub fn undo(&mut self) -> bool {
+        if self.group.is_some() {
+            return false;
+        }
+        self.pending.push(Queued::Undo);
+        true
+    }
+
+    /// queues the last undone step again, not while a group is open
+    pub fn redo(&mut self) -> bool {
+        if self.group.is_some() {
+            return false;
+        }
+        self.pending.push(Queued::Redo);
+        true
+    }
+
+    /// forgets every step, pending edits are still applied
+    pub fn clear(&mut self) {
+        self.done.clear();
+        self.undone.clear();
+        self.group = None;
+        self.last_group = None;
+        self.pending.retain(|it| matches!(it, Queued::New(_, _)));
+    }
+
+    /// Hands the queued edits to `apply`, which returns them as they were applied
+    /// or `None` if they could not be. Only applied edits are recorded.
+    pub fn apply_pending(&mut self, mut apply: impl FnMut(E) -> Option<E>) {
+        for queued in std::mem::take(&mut self.pending) {
+            match queued {
+                Queued::New(edit, group) => {
+                    if let Some(applied) = apply(edit) {
+                        self.undone.clear();
+                        match self.done.last_mut() {
+                            Some(step) if group.is_some() && group == self.last_group => {
+                                step.push(applied)
+                            }
+                            _ => {
+                                self.done.push(vec![applied]);
+                                self.last_group = group;
+                            }
+                        }
+                    }
+                }
+                Queued::Undo => {
+                    if let Some(mut step) = self.done.pop() {
+                        // in reverse order, the inverses of the applied inverses redo the step
+                        let mut redo = Vec::new();
+                        while let Some(edit) = step.pop() {
+                            let queued = edit.inverse();
+                            if let Some(applied) = apply(queued.clone()) {
+                                let edits = self.recorded().chain(&mut step).chain(&mut redo);
+                                remap(edits, &queued, &applied);
+                                redo.push(applied.inverse());
+                            }
+                        }
+                        redo.reverse();
+                        self.last_group = None;
+                        if !redo.is_empty() {
+                            self.undone.push(redo);
+                        }
+                    }
+                }
+                Queued::Redo => {
+                    if let Some(mut step) = self.undone.pop() {
+                        step.reverse();
+                        let mut redone = Vec::new();
+                        while let Some(queued) = step.pop() {
+                            if let Some(applied) = apply(queued.clone()) {
+                                let edits = self.recorded().chain(&mut step).chain(&mut redone);
+                                remap(edits, &queued, &applied);
+                                redone.push(applied);
+                            }
+                        }
+                        self.last_group = None;
+                        if !redone.is_empty() {
+                            self.done.push(redone);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn recorded(&mut self) -> impl Iterator<Item = &mut E> {
+        self.done.iter_mut().chain(self.undone.iter_mut()).flatten()
+    }
+}
+
+fn remap<'a, E: Edit>(edits: impl Iterator<Item = &'a mut E>, queued: &E, applied: &E) {
+    for edit in edits {
+        edit.remap(queued, applied);
+    }
+}
+
+pub fn undo_keys_system<E: Edit>(keys: Res<Input<KeyCode>>, mut undo: ResMut<UndoStack<E>>) {
+    if !(keys.pressed(KeyCode::LControl) || keys.pressed(KeyCode::RControl)) {
+        return;
+    }
+    if keys.just_pressed(KeyCode::Z) {
+        undo.undo();
+    } else if keys.just_pressed(KeyCode::Y) {
+        undo.redo();
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+
+/// `UndoPlugin` for edits of buildings on the `MapCache` grid.
+pub struct MapEditPlugin;
+impl Plugin for MapEditPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(UndoPlugin::<MapEdit>::default())
+            .add_system(map_edit_system.exclusive_system());
+    }
+}
+
+/// Buildings with their items and state, a removed one is placed again just like it was.
+#[derive(Debug, Clone)]
+pub enum MapEdit {
+    /// the building and the entity it was placed as once it is placed,
+    /// placed again it is the entity it was before
+    Place(BuildingSave, Option<Entity>),
+    /// the building to remove and what it held, once it is removed
+    Remove(Entity, Option<BuildingSave>),
+}
+
+impl Edit for MapEdit {
+    /// only edits as they were applied can be inverted
+    fn inverse(&self) -> Self {
+        match self {
+            MapEdit::Place(building, Some(entity)) => {
+                MapEdit::Remove(*entity, Some(building.clone()))
+            }
+            MapEdit::Remove(entity, Some(building)) => {
+                MapEdit::Place(building.clone(), Some(*entity))
+            }
+            other => panic!("{:?} was not applied", other),
+        }
+    }
+
+    /// a building placed again is a new entity
+    fn remap(&mut self, queued: &Self, applied: &Self) {
+        if let (MapEdit::Place(_, Some(old)), MapEdit::Place(_, Some(new))) = (queued, applied) {
+            match self {
+                MapEdit::Place(_, Some(entity)) | MapEdit::Remove(entity, _) if *entity == *old => {
+                    *entity = *new
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Applies the queued `MapEdit`s, places go through `simple_spawner_system`
+/// and get their state back from `restore_system`.
+pub fn map_edit_system(world: &mut World) {
+    if world.get_resource::<UndoStack<MapEdit>>().is_none() {
+        return;
+    }
+    // cells of buildings placed now, they are not on the map yet
+    let mut claimed: HashSet<MapPos> = HashSet::default();
+
+    world.resource_scope(|world, mut undo: Mut<UndoStack<MapEdit>>| {
+        undo.apply_pending(|edit| match edit {
+            MapEdit::Place(building, _) => {
+                let cells = building_cells(&building.simple);
+                let map = world.get_resource::<MapCache>()?;
+                let free = cells
+                    .iter()
+                    .all(|cell| !claimed.contains(cell) && map.is_free(cell));
+                if cells.is_empty() || !free {
+                    return None;
+                }
+                claimed.extend(cells);
+                let entity = world
+                    .spawn()
+                    .insert(building.simple.clone())
+                    .insert(Restore(building.clone()))
+                    .id();
+                Some(MapEdit::Place(building, Some(entity)))
+            }
+            MapEdit::Remove(entity, _) => {
+                let building = BuildingSave::capture(world, entity)?;
+                despawn_building(world, entity);
+                Some(MapEdit::Remove(entity, Some(building)))
+            }
+        });
+    });
+}
+
+/// the cells `simple` is placed on
+fn building_cells(simple: &Simple) -> Vec<MapPos> {
+    let footprint = match simple {
+        Simple::Powered(_, simple) => simple.footprint(),
+        simple => simple.footprint(),
+    };
+    footprint.map_or_else(Vec::new, |(pos, footprint)| {
+        footprint.cells_at(pos).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// like `MapEdit` with named things placed under ids counted up
+    #[derive(Debug, Clone)]
+    enum TestEdit {
+        Place(char, Option<u32>),
+        Remove(u32, Option<char>),
+    }
+
+    impl Edit for TestEdit {
+        fn inverse(&self) -> Self {
+            match self {
+                TestEdit::Place(name, Some(id)) => TestEdit::Remove(*id, Some(*name)),
+                TestEdit::Remove(id, Some(name)) => TestEdit::Place(*name, Some(*id)),
+                other => panic!("{:?} was not applied", other),
+            }
+        }
+
+        fn remap(&mut self, queued: &Self, applied: &Self) {
+            if let (TestEdit::Place(_, Some(old)), TestEdit::Place(_, Some(new))) =
+                (queued, applied)
+            {
+                match self {
+                    TestEdit::Place(_, Some(id)) | TestEdit::Remove(id, _) if *id == *old => {
+                        *id = *new
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct TestWorld {
+        next: u32,
+        placed: HashMap<u32, char>,
+    }
+
+    impl TestWorld {
+        fn apply(&mut self, undo: &mut UndoStack<TestEdit>) {
+            let next = &mut self.next;
+            let placed = &mut self.placed;
+            undo.apply_pending(|edit| match edit {
+                TestEdit::Place(name, _) => {
+                    *next += 1;
+                    placed.insert(*next, name);
+                    Some(TestEdit::Place(name, Some(*next)))
+                }
+                TestEdit::Remove(id, _) => placed
+                    .remove(&id)
+                    .map(|name| TestEdit::Remove(id, Some(name))),
+            });
+        }
+
+        fn names(&self) -> Vec<char> {
+            let mut names: Vec<char> = self.placed.values().copied().collect();
+            names.sort_unstable();
+            names
+        }
+
+        fn id_of(&self, name: char) -> u32 {
+            self.placed
+                .iter()
+                .find(|(_, it)| **it == name)
+                .map(|(id, _)| *id)
+                .unwrap()
+        }
+    }
+
+    #[test]
+    fn a_group_is_one_step() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.begin_group();
+        undo.push(TestEdit::Place('a', None));
+        undo.push(TestEdit::Place('b', None));
+        undo.end_group();
+        undo.push(TestEdit::Place('c', None));
+        world.apply(&mut undo);
+        assert_eq!(vec!['a', 'b', 'c'], world.names());
+
+        undo.undo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['a', 'b'], world.names());
+        undo.undo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+
+        undo.redo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['a', 'b'], world.names());
+    }
+
+    #[test]
+    fn groups_applied_over_several_frames_are_one_step() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.push(TestEdit::Place('a', None));
+        world.apply(&mut undo);
+        undo.begin_group();
+        undo.push(TestEdit::Place('b', None));
+        world.apply(&mut undo);
+        undo.push(TestEdit::Place('c', None));
+        world.apply(&mut undo);
+        undo.end_group();
+
+        undo.undo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['a'], world.names());
+    }
+
+    #[test]
+    fn no_undo_in_the_middle_of_a_group() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.begin_group();
+        undo.push(TestEdit::Place('a', None));
+        world.apply(&mut undo);
+        assert!(!undo.undo());
+        assert!(!undo.redo());
+        undo.push(TestEdit::Place('b', None));
+        world.apply(&mut undo);
+        undo.end_group();
+        assert_eq!(vec!['a', 'b'], world.names());
+
+        assert!(undo.undo());
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+    }
+
+    #[test]
+    fn edits_which_are_not_applied_are_not_recorded() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.push(TestEdit::Place('a', None));
+        undo.push(TestEdit::Remove(42, None));
+        world.apply(&mut undo);
+
+        undo.undo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_undone_steps() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.push(TestEdit::Place('a', None));
+        world.apply(&mut undo);
+        undo.undo();
+        world.apply(&mut undo);
+        undo.push(TestEdit::Place('b', None));
+        world.apply(&mut undo);
+
+        undo.redo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['b'], world.names());
+    }
+
+    #[test]
+    fn place_remove_undo_and_redo_follow_the_new_ids() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.push(TestEdit::Place('a', None));
+        world.apply(&mut undo);
+        let first = world.id_of('a');
+        undo.push(TestEdit::Remove(first, None));
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+
+        // placed again under a new id, which the place before refers to now
+        undo.undo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['a'], world.names());
+        assert_ne!(first, world.id_of('a'));
+        undo.undo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+
+        undo.redo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['a'], world.names());
+        undo.redo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+
+        undo.undo();
+        world.apply(&mut undo);
+        undo.undo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+        undo.redo();
+        undo.redo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+    }
+
+    #[test]
+    fn a_step_which_removes_what_it_placed_is_undone() {
+        let mut world = TestWorld::default();
+        let mut undo = UndoStack::default();
+        undo.begin_group();
+        undo.push(TestEdit::Place('a', None));
+        world.apply(&mut undo);
+        undo.push(TestEdit::Remove(world.id_of('a'), None));
+        undo.push(TestEdit::Place('b', None));
+        world.apply(&mut undo);
+        undo.end_group();
+        assert_eq!(vec!['b'], world.names());
+
+        undo.undo();
+        world.apply(&mut undo);
+        assert!(world.names().is_empty());
+        undo.redo();
+        world.apply(&mut undo);
+        assert_eq!(vec!['b'], world.names());
+    }
+}